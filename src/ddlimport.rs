@@ -0,0 +1,1187 @@
+extern crate nom;
+extern crate serde;
+extern crate rson_rs;
+extern crate clap;
+extern crate appgen;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::process;
+
+use nom::*;
+use nom::types::CompleteStr;
+
+use appgen::schema_spec::*;
+use appgen::sql_gen::*;
+use clap::{Arg, App};
+
+
+// Reads the table definitions out of `pg_dump --schema-only` output and
+// writes them back out as a SchemaSpec. Views, functions, triggers and
+// grants are not brought across, apart from what the generator itself
+// makes out of the api list and the audit and soft_delete options
+
+
+#[derive(Debug, Clone, PartialEq)]
+struct QName(String, String);
+
+#[derive(Debug)]
+enum ConstraintDef {
+    PrimaryKey(Vec<String>),
+    Unique(Vec<String>),
+    Check(String),
+    // columns, referenced table, referenced columns, ON DELETE etc
+    ForeignKey(Vec<String>, QName, Vec<String>, Vec<String>),
+}
+
+#[derive(Debug)]
+enum ColumnClause {
+    NotNull,
+    Null,
+    Default(String),
+    Identity,
+    Generated(String),
+    PrimaryKey,
+    Unique,
+    Check(String),
+    References(QName, Vec<String>, Vec<String>),
+    Ignored,
+}
+
+#[derive(Debug)]
+struct ColumnDef {
+    name: String,
+    sql_type: String,
+    clauses: Vec<ColumnClause>,
+}
+
+#[derive(Debug)]
+enum TableElement {
+    Column(ColumnDef),
+    Constraint(Option<String>, ConstraintDef),
+    Other(String),
+}
+
+#[derive(Debug)]
+enum Statement {
+    CreateTable(QName, Vec<TableElement>),
+    AddConstraint(QName, Option<String>, ConstraintDef),
+    SetDefault(QName, String, String),
+    AddIdentity(QName, String),
+    CreateIndex(bool, Option<String>, QName, Option<Vec<String>>),
+    CreateEnum(QName, Vec<String>),
+    CreateView(QName),
+    Other(String),
+}
+
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+named!(sp<CompleteStr, CompleteStr>, call!(multispace0));
+
+// A whole word, case insensitive
+fn keyword<'a>(input: CompleteStr<'a>, word: &'static str) -> IResult<CompleteStr<'a>, CompleteStr<'a>> {
+    do_parse!(input,
+        sp >>
+        w: tag_no_case!(word) >>
+        not!(take_while1!(is_ident_char)) >>
+        (w))
+}
+
+named!(bare_ident<CompleteStr, String>,
+    map!(take_while1!(is_ident_char), |s: CompleteStr| s.0.to_lowercase()));
+
+named!(quoted_ident<CompleteStr, String>,
+    map!(delimited!(char!('"'), recognize!(many0!(alt!(tag!("\"\"") | is_not!("\"")))), char!('"')),
+         |s: CompleteStr| s.0.replace("\"\"", "\"")));
+
+named!(ident<CompleteStr, String>, preceded!(sp, alt!(quoted_ident | bare_ident)));
+
+// Unqualified names are in public
+named!(qname<CompleteStr, QName>, do_parse!(
+    a: ident >>
+    b: opt!(preceded!(char!('.'), ident)) >>
+    (match b {
+        Some(b) => QName(a, b),
+        None => QName("public".into(), a)
+    })));
+
+named!(column_list<CompleteStr, Vec<String>>, delimited!(
+    preceded!(sp, char!('(')),
+    separated_list!(preceded!(sp, char!(',')), ident),
+    preceded!(sp, char!(')'))));
+
+named!(string_literal<CompleteStr, String>, preceded!(sp,
+    map!(delimited!(char!('\''), recognize!(many0!(alt!(tag!("''") | is_not!("'")))), char!('\'')),
+         |s: CompleteStr| s.0.replace("''", "'"))));
+
+// The text between a bracket and its match, quotes can hold brackets
+fn parens(input: CompleteStr) -> IResult<CompleteStr, CompleteStr> {
+    let (input, _) = preceded!(input, sp, char!('('))?;
+    let mut depth = 1;
+    let mut quote: Option<char> = None;
+    for (i, c) in input.0.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok((CompleteStr(&input.0[i + 1..]), CompleteStr(&input.0[..i])));
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(Err::Error(error_position!(input, ErrorKind::Custom(0))))
+}
+
+const CLAUSE_WORDS: &[&str] = &["not", "null", "default", "constraint", "primary", "unique",
+                                "check", "references", "collate", "generated"];
+
+// Everything up to the next column clause, a type or a default
+fn until_clause(input: CompleteStr) -> IResult<CompleteStr, CompleteStr> {
+    let s = input.0;
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut prev = ' ';
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (None, '(') | (None, '[') => depth += 1,
+            (None, ')') | (None, ']') => depth -= 1,
+            (None, _) if depth == 0 && prev.is_whitespace() && is_ident_char(c) => {
+                let word = s[i..].split(|c: char| !is_ident_char(c)).next().unwrap_or("").to_lowercase();
+                if CLAUSE_WORDS.contains(&word.as_str()) {
+                    return Ok((CompleteStr(&s[i..]), CompleteStr(s[..i].trim())));
+                }
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    Ok((CompleteStr(""), CompleteStr(s.trim())))
+}
+
+named!(referential_action<CompleteStr, CompleteStr>, alt!(
+    recognize!(pair!(call!(keyword, "set"), alt!(call!(keyword, "null") | call!(keyword, "default")))) |
+    recognize!(pair!(call!(keyword, "no"), call!(keyword, "action"))) |
+    call!(keyword, "cascade") |
+    call!(keyword, "restrict")));
+
+// What follows REFERENCES t (cols), one string per option
+named!(fk_options<CompleteStr, Vec<String>>, many0!(map!(alt!(
+    recognize!(tuple!(call!(keyword, "on"), alt!(call!(keyword, "delete") | call!(keyword, "update")), referential_action)) |
+    recognize!(pair!(opt!(call!(keyword, "not")), call!(keyword, "deferrable"))) |
+    recognize!(pair!(call!(keyword, "initially"), alt!(call!(keyword, "deferred") | call!(keyword, "immediate")))) |
+    recognize!(pair!(call!(keyword, "match"), ident)) |
+    recognize!(pair!(call!(keyword, "not"), call!(keyword, "valid")))),
+    |s: CompleteStr| s.0.split_whitespace().collect::<Vec<&str>>().join(" ").to_uppercase())));
+
+named!(constraint_def<CompleteStr, ConstraintDef>, alt!(
+    do_parse!(call!(keyword, "primary") >> call!(keyword, "key") >> c: column_list >>
+              (ConstraintDef::PrimaryKey(c))) |
+    do_parse!(call!(keyword, "unique") >> c: column_list >>
+              (ConstraintDef::Unique(c))) |
+    do_parse!(call!(keyword, "check") >> e: parens >>
+              (ConstraintDef::Check(e.0.trim().to_string()))) |
+    do_parse!(call!(keyword, "foreign") >> call!(keyword, "key") >> c: column_list >>
+              call!(keyword, "references") >> t: qname >> r: opt!(column_list) >> o: fk_options >>
+              (ConstraintDef::ForeignKey(c, t, r.unwrap_or_default(), o)))));
+
+named!(table_constraint<CompleteStr, (Option<String>, ConstraintDef)>, do_parse!(
+    name: opt!(preceded!(call!(keyword, "constraint"), ident)) >>
+    def: constraint_def >>
+    (name, def)));
+
+named!(generated_as<CompleteStr, ()>, do_parse!(
+    call!(keyword, "generated") >>
+    alt!(call!(keyword, "always") | recognize!(pair!(call!(keyword, "by"), call!(keyword, "default")))) >>
+    call!(keyword, "as") >>
+    ()));
+
+named!(column_clause<CompleteStr, ColumnClause>, alt!(
+    value!(ColumnClause::NotNull, pair!(call!(keyword, "not"), call!(keyword, "null"))) |
+    value!(ColumnClause::Null, call!(keyword, "null")) |
+    do_parse!(call!(keyword, "default") >> sp >> e: alt!(call!(keyword, "null") | until_clause) >>
+              (ColumnClause::Default(e.0.to_string()))) |
+    value!(ColumnClause::Identity, tuple!(generated_as, call!(keyword, "identity"), opt!(parens))) |
+    do_parse!(generated_as >> e: parens >> opt!(call!(keyword, "stored")) >>
+              (ColumnClause::Generated(e.0.trim().to_string()))) |
+    value!(ColumnClause::Ignored, pair!(call!(keyword, "constraint"), ident)) |
+    value!(ColumnClause::PrimaryKey, pair!(call!(keyword, "primary"), call!(keyword, "key"))) |
+    value!(ColumnClause::Unique, call!(keyword, "unique")) |
+    do_parse!(call!(keyword, "check") >> e: parens >>
+              (ColumnClause::Check(e.0.trim().to_string()))) |
+    do_parse!(call!(keyword, "references") >> t: qname >> c: opt!(column_list) >> o: fk_options >>
+              (ColumnClause::References(t, c.unwrap_or_default(), o))) |
+    value!(ColumnClause::Ignored, pair!(call!(keyword, "collate"), qname))));
+
+named!(column_def<CompleteStr, ColumnDef>, do_parse!(
+    name: ident >>
+    sp >>
+    t: until_clause >>
+    clauses: many0!(column_clause) >>
+    sp >>
+    eof!() >>
+    (ColumnDef { name: name, sql_type: t.0.to_string(), clauses: clauses })));
+
+fn table_element(text: &str) -> TableElement {
+    let input = CompleteStr(text);
+    if let Ok((_, (name, def))) = table_constraint(input) {
+        return TableElement::Constraint(name, def);
+    }
+    match column_def(input) {
+        Ok((_, c)) => TableElement::Column(c),
+        Err(_) => TableElement::Other(text.trim().to_string())
+    }
+}
+
+named!(create_table<CompleteStr, Statement>, do_parse!(
+    call!(keyword, "create") >>
+    opt!(alt!(call!(keyword, "unlogged") | call!(keyword, "temporary") | call!(keyword, "temp"))) >>
+    call!(keyword, "table") >>
+    opt!(tuple!(call!(keyword, "if"), call!(keyword, "not"), call!(keyword, "exists"))) >>
+    name: qname >>
+    body: parens >>
+    (Statement::CreateTable(name, split_top(body.0).iter().map(|e| table_element(e)).collect()))));
+
+named!(alter_table<CompleteStr, QName>, do_parse!(
+    call!(keyword, "alter") >>
+    call!(keyword, "table") >>
+    opt!(tuple!(call!(keyword, "if"), call!(keyword, "exists"))) >>
+    opt!(call!(keyword, "only")) >>
+    name: qname >>
+    (name)));
+
+named!(add_constraint<CompleteStr, Statement>, do_parse!(
+    t: alter_table >>
+    call!(keyword, "add") >>
+    c: table_constraint >>
+    (Statement::AddConstraint(t, c.0, c.1))));
+
+named!(alter_column<CompleteStr, (QName, String)>, do_parse!(
+    t: alter_table >>
+    call!(keyword, "alter") >>
+    opt!(call!(keyword, "column")) >>
+    c: ident >>
+    (t, c)));
+
+named!(set_default<CompleteStr, Statement>, do_parse!(
+    c: alter_column >>
+    call!(keyword, "set") >>
+    call!(keyword, "default") >>
+    sp >>
+    e: until_clause >>
+    (Statement::SetDefault(c.0, c.1, e.0.to_string()))));
+
+named!(add_identity<CompleteStr, Statement>, do_parse!(
+    c: alter_column >>
+    call!(keyword, "add") >>
+    generated_as >>
+    call!(keyword, "identity") >>
+    (Statement::AddIdentity(c.0, c.1))));
+
+named!(create_index<CompleteStr, Statement>, do_parse!(
+    call!(keyword, "create") >>
+    unique: opt!(call!(keyword, "unique")) >>
+    call!(keyword, "index") >>
+    opt!(call!(keyword, "concurrently")) >>
+    opt!(tuple!(call!(keyword, "if"), call!(keyword, "not"), call!(keyword, "exists"))) >>
+    name: opt!(preceded!(not!(call!(keyword, "on")), ident)) >>
+    call!(keyword, "on") >>
+    opt!(call!(keyword, "only")) >>
+    t: qname >>
+    opt!(pair!(call!(keyword, "using"), ident)) >>
+    cols: opt!(terminated!(column_list, pair!(sp, eof!()))) >>
+    (Statement::CreateIndex(unique.is_some(), name, t, cols))));
+
+named!(create_enum<CompleteStr, Statement>, do_parse!(
+    call!(keyword, "create") >>
+    call!(keyword, "type") >>
+    name: qname >>
+    call!(keyword, "as") >>
+    call!(keyword, "enum") >>
+    values: delimited!(
+        preceded!(sp, char!('(')),
+        separated_list!(preceded!(sp, char!(',')), string_literal),
+        preceded!(sp, char!(')'))) >>
+    (Statement::CreateEnum(name, values))));
+
+named!(create_view<CompleteStr, Statement>, do_parse!(
+    call!(keyword, "create") >>
+    opt!(pair!(call!(keyword, "or"), call!(keyword, "replace"))) >>
+    call!(keyword, "view") >>
+    name: qname >>
+    (Statement::CreateView(name))));
+
+named!(statement<CompleteStr, Statement>, alt!(
+    create_table | add_constraint | set_default | add_identity | create_index | create_enum | create_view));
+
+
+// Splits on sep where it is outside quotes and brackets
+fn split_top(s: &str) -> Vec<String> {
+    let mut out = vec!();
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                out.push(s[start..i].trim().to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if s[start..].trim().len() > 0 {
+        out.push(s[start..].trim().to_string());
+    }
+    out
+}
+
+// The dump split into statements, comments taken out. Function bodies
+// between $tag$ quotes are kept whole
+fn statements(dump: &str) -> Vec<String> {
+    let mut out = vec!();
+    let mut current = String::new();
+    let mut chars = dump.char_indices().peekable();
+    let mut quote: Option<char> = None;
+    let mut dollar: Option<String> = None;
+
+    while let Some((i, c)) = chars.next() {
+        let rest = &dump[i..];
+        if let Some(tag) = dollar.clone() {
+            current.push(c);
+            if rest.starts_with(&tag) {
+                current.push_str(&tag[1..]);
+                for _ in 1..tag.len() {
+                    chars.next();
+                }
+                dollar = None;
+            }
+            continue;
+        }
+        match (quote, c) {
+            (Some(q), c) if c == q => {
+                quote = None;
+                current.push(c);
+            }
+            (Some(_), _) => current.push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                current.push(c);
+            }
+            (None, '-') if rest.starts_with("--") => {
+                while let Some(&(_, n)) = chars.peek() {
+                    if n == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+            (None, '/') if rest.starts_with("/*") => {
+                let end = rest.find("*/").map(|e| e + 2).unwrap_or(rest.len());
+                while chars.peek().map(|&(j, _)| j < i + end).unwrap_or(false) {
+                    chars.next();
+                }
+                current.push(' ');
+            }
+            (None, '$') => {
+                let tag_len = rest[1..].find('$').filter(|n| rest[1..1 + n].chars().all(is_ident_char));
+                match tag_len {
+                    Some(n) => {
+                        let tag = rest[..n + 2].to_string();
+                        current.push_str(&tag);
+                        for _ in 1..tag.len() {
+                            chars.next();
+                        }
+                        dollar = Some(tag);
+                    }
+                    None => current.push(c)
+                }
+            }
+            (None, ';') => {
+                if current.trim().len() > 0 {
+                    out.push(current.trim().to_string());
+                }
+                current = String::new();
+            }
+            _ => current.push(c)
+        }
+    }
+    if current.trim().len() > 0 {
+        out.push(current.trim().to_string());
+    }
+    out
+}
+
+fn parse_statement(s: &str) -> Statement {
+    match statement(CompleteStr(s)) {
+        Ok((_, st)) => st,
+        Err(_) => Statement::Other(s.to_string())
+    }
+}
+
+
+fn warn(msg: String) {
+    eprintln!("warning: {}", msg);
+}
+
+// The mapped type and any override needed to keep the column's exact
+// type, the bool is a serial type
+fn map_type(text: &str, enums: &Vec<(QName, Vec<String>)>) -> (MappedFieldType, Vec<SqlOption>, bool) {
+    let lower = text.split_whitespace().collect::<Vec<&str>>().join(" ").to_lowercase();
+    if lower.ends_with("[]") {
+        let (inner, _, _) = map_type(&lower[..lower.len() - 2], enums);
+        return (MappedFieldType::Array(Box::new(inner)), vec!(), false);
+    }
+
+    let name = lower.trim_start_matches("pg_catalog.").to_string();
+    let (base, args) = match (name.find('('), name.rfind(')')) {
+        (Some(open), Some(close)) if open < close => {
+            let args = name[open + 1..close].split(',').map(|a| a.trim().parse::<usize>().unwrap_or(0)).collect::<Vec<usize>>();
+            (format!("{}{}", name[..open].trim(), &name[close + 1..]), args)
+        }
+        _ => (name.clone(), vec!())
+    };
+
+    let overridden = |t: MappedFieldType| (t, vec!(SqlOption::TypeOverride(text.to_string())), false);
+    match (base.as_str(), args.as_slice()) {
+        ("smallint", _) | ("int2", _) => (MappedFieldType::SmallInt, vec!(), false),
+        ("integer", _) | ("int", _) | ("int4", _) => overridden(MappedFieldType::Integer),
+        ("bigint", _) | ("int8", _) => (MappedFieldType::Integer, vec!(), false),
+        ("serial", _) | ("serial4", _) | ("smallserial", _) | ("serial2", _) => {
+            (MappedFieldType::Integer, vec!(SqlOption::TypeOverride(text.to_string())), true)
+        }
+        ("bigserial", _) | ("serial8", _) => (MappedFieldType::Integer, vec!(), true),
+        ("boolean", _) | ("bool", _) => (MappedFieldType::Boolean, vec!(), false),
+        ("text", _) => (MappedFieldType::Text, vec!(), false),
+        ("uuid", _) => (MappedFieldType::Uuid, vec!(), false),
+        ("json", _) => (MappedFieldType::Json, vec!(), false),
+        ("jsonb", _) => (MappedFieldType::Jsonb, vec!(), false),
+        ("bytea", _) => (MappedFieldType::Bytea, vec!(), false),
+        ("character varying", &[n]) | ("varchar", &[n]) => (MappedFieldType::String(n), vec!(), false),
+        ("character varying", _) | ("varchar", _) => (MappedFieldType::Text, vec!(), false),
+        ("character", _) | ("char", _) | ("bpchar", _) => overridden(MappedFieldType::String(*args.first().unwrap_or(&1))),
+        ("numeric", &[p, s]) | ("decimal", &[p, s]) => (MappedFieldType::Decimal(p, s), vec!(), false),
+        ("numeric", &[p]) | ("decimal", &[p]) => (MappedFieldType::Decimal(p, 0), vec!(), false),
+        ("numeric", _) | ("decimal", _) => (MappedFieldType::Numeric, vec!(), false),
+        ("real", _) | ("float4", _) | ("double precision", _) | ("float8", _) => overridden(MappedFieldType::Numeric),
+        ("timestamp with time zone", _) | ("timestamptz", _) => (MappedFieldType::Timestamp(Some("UTC".into())), vec!(), false),
+        ("timestamp without time zone", _) | ("timestamp", _) => (MappedFieldType::Timestamp(None), vec!(), false),
+        ("date", _) => (MappedFieldType::Date, vec!(), false),
+        ("time without time zone", _) | ("time", _) => (MappedFieldType::Time, vec!(), false),
+        ("interval", _) => (MappedFieldType::Interval, vec!(), false),
+        _ => {
+            let unqualified = base.rsplit('.').next().unwrap_or("");
+            match enums.iter().find(|e| e.0 .1 == unqualified) {
+                Some(e) => (MappedFieldType::Enum(e.1.iter().map(|v| (v.clone(), v.clone())).collect()), vec!(), false),
+                None => {
+                    warn(format!("no mapped type for {}, kept as a type override on Text", text));
+                    overridden(MappedFieldType::Text)
+                }
+            }
+        }
+    }
+}
+
+// A default as the schema file writes it, casts and quotes taken off
+fn map_default(expr: &str) -> String {
+    let mut e = expr.trim().to_string();
+    while let Some(i) = e.rfind("::") {
+        let tail = &e[i + 2..];
+        if tail.contains('\'') || tail.contains(')') {
+            break;
+        }
+        e = e[..i].trim().to_string();
+        if e.starts_with('(') && e.ends_with(')') {
+            e = e[1..e.len() - 1].to_string();
+        }
+    }
+    if e.len() >= 2 && e.starts_with('\'') && e.ends_with('\'') {
+        return e[1..e.len() - 1].replace("''", "'");
+    }
+    e
+}
+
+fn is_sequence_default(expr: &str) -> bool {
+    expr.trim().to_lowercase().starts_with("nextval(")
+}
+
+// The values of a check postgres wrote back out from an enum's IN list
+fn enum_check(expr: &str) -> Option<Vec<String>> {
+    let lower = expr.to_lowercase();
+    let list = if lower.contains("= any") && lower.contains("array[") {
+        &expr[lower.find("array[").unwrap() + 6..]
+    } else if lower.contains(" in (") {
+        &expr[lower.find(" in (").unwrap() + 5..]
+    } else {
+        return None;
+    };
+    let mut values = vec!();
+    let mut rest = CompleteStr(list);
+    loop {
+        match string_literal(rest) {
+            Ok((r, v)) => {
+                values.push(v);
+                let after = r.0.trim_start();
+                let after = if after.starts_with("::") {
+                    after[2..].trim_start_matches(|c: char| is_ident_char(c) || c == ' ' || c == '[' || c == ']')
+                } else {
+                    after
+                };
+                if after.starts_with(',') {
+                    rest = CompleteStr(&after[1..]);
+                } else {
+                    break;
+                }
+            }
+            Err(_) => return None
+        }
+    }
+    if values.len() > 0 { Some(values) } else { None }
+}
+
+fn mentions(expr: &str, column: &str) -> bool {
+    expr.split(|c: char| !is_ident_char(c)).any(|w| w.trim_matches('"') == column)
+}
+
+
+struct Builder {
+    tables: Vec<(QName, Vec<Field>)>,
+    relationships: Vec<Relation>,
+    enums: Vec<(QName, Vec<String>)>,
+    // (table, column) given a sequence
+    serials: Vec<(QName, String)>,
+}
+
+impl Builder {
+    fn table(&mut self, name: &QName) -> Option<&mut Vec<Field>> {
+        self.tables.iter_mut().find(|t| t.0 == *name).map(|t| &mut t.1)
+    }
+
+    fn field(&mut self, table: &QName, column: &str) -> Option<&mut Field> {
+        self.table(table).and_then(|fs| fs.iter_mut().find(|f| f.0 == column))
+    }
+
+    fn column(&mut self, table: &QName, c: &ColumnDef) -> Field {
+        let (ty, mut sql, serial) = map_type(&c.sql_type, &self.enums);
+        let mut o = Options { null: Nullable::Null, ..Default::default() };
+        if serial {
+            self.serials.push((table.clone(), c.name.clone()));
+            o.null = Nullable::NotNull;
+        }
+        let mut checks = vec!();
+        for clause in c.clauses.iter() {
+            match clause {
+                ColumnClause::NotNull => o.null = Nullable::NotNull,
+                ColumnClause::Null => o.null = Nullable::Null,
+                ColumnClause::Default(e) if e.eq_ignore_ascii_case("null") => {}
+                ColumnClause::Default(e) if is_sequence_default(e) => self.serials.push((table.clone(), c.name.clone())),
+                ColumnClause::Default(e) => o.default_value = map_default(e),
+                ColumnClause::Identity => {
+                    self.serials.push((table.clone(), c.name.clone()));
+                    o.null = Nullable::NotNull;
+                }
+                // pg_dump puts the expression in brackets of its own
+                ColumnClause::Generated(e) => o.computed = match parens(CompleteStr(e)) {
+                    Ok((rest, inner)) if rest.trim().len() == 0 => inner.0.trim().to_string(),
+                    _ => e.clone()
+                },
+                ColumnClause::PrimaryKey => {
+                    o.primary_key = true;
+                    o.null = Nullable::NotNull;
+                }
+                ColumnClause::Unique => sql.push(SqlOption::Unique("".into())),
+                ColumnClause::Check(e) => checks.push(e.clone()),
+                ColumnClause::References(t, cols, opts) => {
+                    self.reference(table, vec!(c.name.clone()), t, cols.clone(), opts);
+                }
+                ColumnClause::Ignored => {}
+            }
+        }
+        let mut f = Field(c.name.clone(), ty, o);
+        f.2.sql = sql;
+        for e in checks {
+            self.check(&mut f, &e);
+        }
+        f
+    }
+
+    fn check(&self, f: &mut Field, expr: &str) {
+        match (enum_check(expr), &f.1) {
+            (Some(values), MappedFieldType::String(_)) |
+            (Some(values), MappedFieldType::Text) => {
+                f.1 = MappedFieldType::Enum(values.into_iter().map(|v| (v.clone(), v)).collect());
+                f.2.sql.retain(|o| match o { SqlOption::TypeOverride(_) => false, _ => true });
+            }
+            _ => f.2.sql.push(SqlOption::Constraint(expr.to_string()))
+        }
+    }
+
+    fn reference(&mut self, table: &QName, cols: Vec<String>, to: &QName, to_cols: Vec<String>, opts: &Vec<String>) {
+        let action = |a: &str| match a {
+            "RESTRICT" => ReferentialAction::Restrict,
+            "CASCADE" => ReferentialAction::Cascade,
+            "SET NULL" => ReferentialAction::SetNull,
+            "SET DEFAULT" => ReferentialAction::SetDefault,
+            _ => ReferentialAction::NoAction,
+        };
+        let (mut on_delete, mut on_update, mut deferrable) = Default::default();
+        for o in opts.iter() {
+            if o.starts_with("ON DELETE ") {
+                on_delete = action(&o[10..]);
+            } else if o.starts_with("ON UPDATE ") {
+                on_update = action(&o[10..]);
+            } else if o == "DEFERRABLE" {
+                deferrable = Deferral::Immediate;
+            } else if o == "INITIALLY DEFERRED" {
+                deferrable = Deferral::Deferred;
+            } else if o != "NOT DEFERRABLE" && o != "INITIALLY IMMEDIATE" {
+                warn(format!("{}({}): {} left off the foreign key", table.1, cols.join(", "), o));
+            }
+        }
+
+        let columns = |mut cs: Vec<String>| if cs.len() == 1 { Columns::Single(cs.remove(0)) } else { Columns::Composite(cs) };
+        let to_cols = if to_cols.len() > 0 {
+            to_cols
+        } else {
+            self.tables.iter().find(|t| t.0 == *to)
+                .map(|t| t.1.iter().filter(|f| f.2.primary_key).map(|f| f.0.clone()).collect::<Vec<String>>())
+                .filter(|cs| cs.len() > 0)
+                .unwrap_or(vec!("id".into()))
+        };
+        self.relationships.push(Relation {
+            from: Cardinality::Many(table.1.clone(), columns(cols)),
+            to: Cardinality::One(to.1.clone(), columns(to_cols)),
+            on_delete: on_delete,
+            on_update: on_update,
+            deferrable: deferrable,
+        });
+    }
+
+    fn constraint(&mut self, table: &QName, name: Option<String>, def: ConstraintDef) {
+        if self.table(table).is_none() {
+            warn(format!("constraint on unknown table {}.{}", table.0, table.1));
+            return;
+        }
+        match def {
+            ConstraintDef::PrimaryKey(cols) => {
+                for c in cols.iter() {
+                    if let Some(f) = self.field(table, c) {
+                        f.2.primary_key = true;
+                        f.2.null = Nullable::NotNull;
+                    }
+                }
+            }
+            ConstraintDef::Unique(cols) => {
+                let default = format!("{}_{}_key", table.1, cols.join("_"));
+                let name = name.filter(|n| cols.len() > 1 || *n != default).unwrap_or(if cols.len() > 1 { default } else { "".into() });
+                for c in cols.iter() {
+                    if let Some(f) = self.field(table, c) {
+                        f.2.sql.push(SqlOption::Unique(name.clone()));
+                    }
+                }
+            }
+            ConstraintDef::Check(expr) => {
+                let fields = self.table(table).unwrap();
+                let i = fields.iter().position(|f| mentions(&expr, &f.0)).unwrap_or(0);
+                let mut f = fields.remove(i);
+                self.check(&mut f, &expr);
+                self.table(table).unwrap().insert(i, f);
+            }
+            ConstraintDef::ForeignKey(cols, to, to_cols, opts) => self.reference(table, cols, &to, to_cols, &opts)
+        }
+    }
+
+    fn index(&mut self, unique: bool, name: Option<String>, table: &QName, cols: Option<Vec<String>>) {
+        let cols = match cols {
+            Some(cols) => cols,
+            None => return warn(format!("index {} on {} is on expressions, left out", name.unwrap_or_default(), table.1))
+        };
+        let default = format!("{}_{}_{}", table.1, cols.join("_"), if unique { "key" } else { "idx" });
+        let name = match name {
+            Some(ref n) if cols.len() == 1 && *n == default => "".into(),
+            Some(n) => n,
+            None if cols.len() == 1 => "".into(),
+            None => default,
+        };
+        for c in cols.iter() {
+            match self.field(table, c) {
+                Some(f) if unique => f.2.sql.push(SqlOption::Unique(name.clone())),
+                Some(f) => f.2.sql.push(SqlOption::Index(name.clone())),
+                None => warn(format!("index {} on unknown column {}.{}", name, table.1, c))
+            }
+        }
+    }
+
+    // A sequence on the lone integer key makes it a serial, anywhere else
+    // the sequence is dropped. Serials are bigint, a narrower key is widened
+    fn serials(&mut self) {
+        for (table, column) in self.serials.clone() {
+            let fields = match self.table(&table) {
+                Some(fields) => fields,
+                None => continue
+            };
+            let keys = fields.iter().filter(|f| f.2.primary_key).count();
+            let f = match fields.iter_mut().find(|f| f.0 == column) {
+                Some(f) => f,
+                None => {
+                    warn(format!("{}.{}: a sequence is on a column that was left out", table.1, column));
+                    continue;
+                }
+            };
+            if keys == 1 && f.2.primary_key {
+                let narrow = f.1 == MappedFieldType::SmallInt || f.2.sql.iter().any(|o| match o { SqlOption::TypeOverride(_) => true, _ => false });
+                if narrow {
+                    warn(format!("{}.{}: serial keys are bigint, widened from {}", table.1, column, match f.1 {
+                        MappedFieldType::SmallInt => "smallint",
+                        _ => "integer"
+                    }));
+                }
+                f.1 = MappedFieldType::BigSerialPk;
+                f.2.primary_key = false;
+                f.2.sql.retain(|o| match o { SqlOption::TypeOverride(_) => false, _ => true });
+            } else {
+                warn(format!("{}.{}: takes its value from a sequence but is not the primary key, the sequence is left out", table.1, column));
+            }
+        }
+    }
+}
+
+fn build(dump: &str) -> Result<SchemaSpec, String> {
+    let mut b = Builder { tables: vec!(), relationships: vec!(), enums: vec!(), serials: vec!() };
+    let mut later = vec!();
+    let mut views = vec!();
+
+    for s in statements(dump).iter() {
+        match parse_statement(s) {
+            Statement::CreateEnum(name, values) => b.enums.push((name, values)),
+            Statement::CreateView(name) => views.push(name),
+            Statement::CreateTable(name, elements) => {
+                if b.tables.iter().any(|t| t.0 .1 == name.1) {
+                    warn(format!("{}.{}: a table called {} is already in, left out", name.0, name.1, name.1));
+                    continue;
+                }
+                b.tables.push((name.clone(), vec!()));
+                for e in elements {
+                    match e {
+                        TableElement::Column(c) => {
+                            let f = b.column(&name, &c);
+                            b.table(&name).unwrap().push(f);
+                        }
+                        TableElement::Constraint(n, c) => later.push(Statement::AddConstraint(name.clone(), n, c)),
+                        TableElement::Other(text) => warn(format!("{}: {} left out", name.1, text))
+                    }
+                }
+            }
+            Statement::Other(text) => {
+                let lower = text.to_lowercase();
+                if lower.starts_with("create table") {
+                    return Err(format!("could not read {}", text.lines().next().unwrap_or("")));
+                }
+                if lower.starts_with("create") && !lower.starts_with("create sequence") && !lower.starts_with("create schema") {
+                    warn(format!("left out: {}", text.lines().next().unwrap_or("")));
+                }
+            }
+            st => later.push(st)
+        }
+    }
+
+    // constraints can come before the table they point at
+    for st in later {
+        match st {
+            Statement::AddConstraint(t, n, c) => b.constraint(&t, n, c),
+            Statement::SetDefault(t, c, e) => {
+                if is_sequence_default(&e) {
+                    b.serials.push((t, c));
+                } else if let Some(f) = b.field(&t, &c) {
+                    f.2.default_value = map_default(&e);
+                }
+            }
+            Statement::AddIdentity(t, c) => b.serials.push((t, c)),
+            Statement::CreateIndex(unique, name, t, cols) => b.index(unique, name, &t, cols),
+            _ => {}
+        }
+    }
+    if b.tables.len() == 0 {
+        return Err("no CREATE TABLE in the dump".into());
+    }
+    b.serials();
+
+    // one schema for every table is the schema's namespace
+    let schemas = b.tables.iter().map(|t| t.0 .0.clone()).collect::<Vec<String>>();
+    let shared = if schemas.len() > 0 && schemas.iter().all(|n| *n == schemas[0] && *n != "public") {
+        schemas[0].clone()
+    } else {
+        "".into()
+    };
+
+    // defaults are read as literals of their column's type, which has to
+    // be one first
+    let mut tables = vec!();
+    for (name, mut fields) in b.tables.into_iter() {
+        for f in fields.iter_mut() {
+            check_type(&f.1).map_err(|e| format!("{}.{}: {}", name.1, f.0, e))?;
+            if f.2.default_value.len() > 0 && sql_literal(Dialect::Postgres, &f.1, &f.2.default_value).is_err() {
+                warn(format!("{}.{}: default {} has no mapping, left out", name.1, f.0, f.2.default_value));
+                f.2.default_value = "".into();
+            }
+        }
+        let namespace = if name.0 == "public" || name.0 == shared { "".into() } else { name.0 };
+        tables.push(Table(name.1, fields, TableOptions { namespace: namespace, ..Default::default() }));
+    }
+
+    let mut s = SchemaSpec {
+        tables: tables,
+        relationships: b.relationships,
+        views: vec!(),
+        api: vec!(),
+        acl: vec!(),
+        namespace: shared,
+        events: vec!(),
+        seed: vec!(),
+    };
+    generated(&mut s, &views);
+    Ok(s)
+}
+
+// Takes what the generator adds back to the options it was made from. A
+// table with the history audit would give it is audited and the history
+// goes, a table with a view of its own in the api namespace is in the api
+// and one of those with a nullable deleted_at timestamp is soft deleted
+fn generated(s: &mut SchemaSpec, views: &[QName]) {
+    let mut histories = vec!();
+    for i in 0..s.tables.len() {
+        if s.tables[i].1.iter().filter(|f| f.is_primary_key()).count() != 1 {
+            continue;
+        }
+        s.tables[i].2.audit = true;
+        let expected = history_table(s, &s.tables[i]).unwrap();
+        let found = s.tables.iter().find(|h| h.0 == expected.0).map(|h| {
+            h.1.len() == expected.1.len() && h.1.iter().zip(expected.1.iter()).all(|(f, e)| f.0 == e.0 && f.1 == e.1)
+        });
+        s.tables[i].2.audit = found == Some(true);
+        if found == Some(true) {
+            histories.push(expected.0);
+        }
+    }
+    s.tables.retain(|t| !histories.contains(&t.0));
+    s.relationships.retain(|r| !histories.contains(r.from.table()) && !histories.contains(r.to.table()));
+
+    s.api = s.tables.iter()
+        .filter(|t| views.iter().any(|v| v.0 == API_NAMESPACE && v.1 == t.0))
+        .map(|t| t.0.clone())
+        .collect();
+    let api = &s.api;
+    for t in s.tables.iter_mut().filter(|t| api.contains(&t.0) && t.1.iter().filter(|f| f.is_primary_key()).count() == 1) {
+        let deleted_at = t.1.iter().position(|f| f.0 == DELETED_AT && f.2.null == Nullable::Null && match f.1 {
+            MappedFieldType::Timestamp(_) => true,
+            _ => false
+        });
+        if let Some(i) = deleted_at {
+            t.1.remove(i);
+            t.2.soft_delete = true;
+        }
+    }
+
+    for v in views.iter() {
+        let made = v.0 == API_NAMESPACE && s.tables.iter().any(|t| s.api.contains(&t.0) && (
+            v.1 == t.0 ||
+            t.2.audit && v.1 == t.history_table_name() ||
+            t.2.soft_delete && v.1 == t.deleted_view_name()));
+        if !made {
+            warn(format!("left out: view {}.{}", v.0, v.1));
+        }
+    }
+}
+
+
+fn rson<T: serde::Serialize>(v: &T) -> String {
+    rson_rs::ser::to_string(v).unwrap()
+}
+
+// Written by hand to leave out everything at its default, the way the
+// schema files are written
+fn options_rson(o: &Options) -> String {
+    let mut parts = vec!();
+    if o.null == Nullable::Null {
+        parts.push("null: Null".to_string());
+    }
+    if o.primary_key {
+        parts.push("primary_key: true".to_string());
+    }
+    if o.default_value.len() > 0 {
+        parts.push(format!("default: {}", rson(&o.default_value)));
+    }
+    if o.computed.len() > 0 {
+        parts.push(format!("computed: {}", rson(&o.computed)));
+    }
+    if o.sql.len() > 0 {
+        parts.push(format!("sql: {}", rson(&o.sql)));
+    }
+    format!("{{{}}}", parts.join(", "))
+}
+
+fn schema_rson(s: &SchemaSpec) -> String {
+    let tables = s.tables.iter().map(|t| {
+        let fields = t.1.iter()
+            .map(|f| format!("    ({}, {}, {}),\n", rson(&f.0), rson(&f.1), options_rson(&f.2)))
+            .collect::<String>();
+        let mut options = vec!();
+        if t.2.namespace.len() > 0 {
+            options.push(format!("namespace: {}", rson(&t.2.namespace)));
+        }
+        if t.2.audit {
+            options.push("audit: true".to_string());
+        }
+        if t.2.soft_delete {
+            options.push("soft_delete: true".to_string());
+        }
+        let options = if options.len() > 0 { format!(", {{{}}}", options.join(", ")) } else { "".into() };
+        format!("  ({}, [\n{}  ]{}),\n", rson(&t.0), fields, options)
+    }).collect::<String>();
+
+    let relationships = s.relationships.iter()
+        .map(|r| {
+            let mut options = String::new();
+            if r.on_delete != ReferentialAction::NoAction {
+                options += &format!(", on_delete: {}", rson(&r.on_delete));
+            }
+            if r.on_update != ReferentialAction::NoAction {
+                options += &format!(", on_update: {}", rson(&r.on_update));
+            }
+            if r.deferrable != Deferral::NotDeferrable {
+                options += &format!(", deferrable: {}", rson(&r.deferrable));
+            }
+            format!("    {{ from: {}, to: {}{} }},\n", rson(&r.from), rson(&r.to), options)
+        })
+        .collect::<String>();
+
+    let namespace = if s.namespace.len() > 0 { format!("  namespace: {},\n", rson(&s.namespace)) } else { "".into() };
+    format!("{{ tables: [\n{}  ],\n  views: [],\n  relationships: [\n{}  ],\n{}  api: {},\n  acl: [],\n}}\n",
+            tables, relationships, namespace, rson(&s.api))
+}
+
+
+fn main() {
+    let matches = App::new("DDL Import")
+        .version("0.1")
+        .about("Write an application specification from a pg_dump --schema-only dump")
+        .arg(Arg::with_name("INPUT")
+                .value_name("SQL_FILE")
+                .help("Schema dump to read")
+                .takes_value(true)
+                .index(1)
+                .required(true))
+        .arg(Arg::with_name("OUTPUT")
+                .value_name("APPSPEC_FILE")
+                .help("Where to write the specification, standard output if left out")
+                .takes_value(true)
+                .index(2)
+                .required(false))
+        .get_matches();
+
+    let filename = matches.value_of("INPUT").unwrap();
+    let mut dump = String::new();
+    File::open(filename).and_then(|mut f| f.read_to_string(&mut dump)).unwrap_or_else(|e| {
+        eprintln!("{}: {}", filename, e);
+        process::exit(1);
+    });
+
+    let schema = build(&dump).unwrap_or_else(|e| {
+        eprintln!("{}: {}", filename, e);
+        process::exit(1);
+    });
+    // checked the way objectdef reads it back
+    let out = schema_rson(&schema);
+    let mut written: SchemaSpec = rson_rs::de::from_str(&out).expect("the specification written reads back");
+    written.add_implied_columns();
+    if let Err(e) = check_schema(Dialect::Postgres, &written) {
+        warn(format!("the imported specification does not check, {}", e));
+    }
+
+    match matches.value_of("OUTPUT") {
+        Some(path) => File::create(path).and_then(|mut f| f.write_all(out.as_bytes())).unwrap(),
+        None => print!("{}", out)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table<'a>(s: &'a SchemaSpec, name: &str) -> &'a Table {
+        s.tables.iter().find(|t| t.0 == name).unwrap()
+    }
+
+    fn field<'a>(t: &'a Table, name: &str) -> &'a Field {
+        t.1.iter().find(|f| f.0 == name).unwrap()
+    }
+
+    #[test]
+    fn create_table() {
+        let s = build("CREATE TABLE public.chore (
+                           id bigint NOT NULL,
+                           name character varying(64) NOT NULL,
+                           note text,
+                           status text CHECK (status IN ('todo', 'done')),
+                           price numeric(8,2) DEFAULT 0.50
+                       );").unwrap();
+        let t = table(&s, "chore");
+        assert_eq!(t.1.len(), 5);
+        assert_eq!(field(t, "id").1, MappedFieldType::Integer);
+        assert_eq!(field(t, "name").1, MappedFieldType::String(64));
+        assert_eq!(field(t, "name").2.null, Nullable::NotNull);
+        assert_eq!(field(t, "note").2.null, Nullable::Null);
+        assert_eq!(field(t, "status").1, MappedFieldType::Enum(vec!(("todo".into(), "todo".into()), ("done".into(), "done".into()))));
+        assert_eq!(field(t, "price").1, MappedFieldType::Decimal(8, 2));
+        assert_eq!(field(t, "price").2.default_value, "0.50");
+    }
+
+    #[test]
+    fn alter_table_add_constraint() {
+        let s = build("CREATE TABLE data.kid (id bigint NOT NULL, name text);
+                       CREATE TABLE data.chore (id bigint NOT NULL, kid_id bigint);
+                       ALTER TABLE ONLY data.kid ADD CONSTRAINT kid_pkey PRIMARY KEY (id);
+                       ALTER TABLE ONLY data.chore ADD CONSTRAINT chore_pkey PRIMARY KEY (id);
+                       ALTER TABLE ONLY data.kid ADD CONSTRAINT kid_name_key UNIQUE (name);
+                       ALTER TABLE ONLY data.chore
+                           ADD CONSTRAINT chore_kid_id_fkey FOREIGN KEY (kid_id) REFERENCES data.kid(id) ON DELETE CASCADE DEFERRABLE;").unwrap();
+        assert_eq!(s.namespace, "data");
+        let kid = table(&s, "kid");
+        assert!(field(kid, "id").2.primary_key);
+        assert_eq!(field(kid, "name").2.sql, vec!(SqlOption::Unique("".into())));
+
+        assert_eq!(s.relationships.len(), 1);
+        let r = &s.relationships[0];
+        assert_eq!(r.from.table(), "chore");
+        assert_eq!(r.from.field_list(), "kid_id");
+        assert_eq!(r.to.table(), "kid");
+        assert_eq!(r.on_delete, ReferentialAction::Cascade);
+        assert_eq!(r.deferrable, Deferral::Immediate);
+    }
+
+    #[test]
+    fn sequences_and_defaults() {
+        let s = build("CREATE TABLE public.chore (
+                           id integer NOT NULL,
+                           done boolean DEFAULT false NOT NULL,
+                           due date DEFAULT CURRENT_DATE,
+                           label character varying(20) DEFAULT 'it''s'::character varying
+                       );
+                       CREATE SEQUENCE public.chore_id_seq AS integer START WITH 1;
+                       ALTER TABLE ONLY public.chore ALTER COLUMN id SET DEFAULT nextval('public.chore_id_seq'::regclass);
+                       ALTER TABLE ONLY public.chore ADD CONSTRAINT chore_pkey PRIMARY KEY (id);
+                       ALTER TABLE public.chore ALTER COLUMN gone ADD GENERATED ALWAYS AS IDENTITY;").unwrap();
+        let t = table(&s, "chore");
+        let id = field(t, "id");
+        assert_eq!(id.1, MappedFieldType::BigSerialPk);
+        assert!(!id.2.primary_key);
+        assert_eq!(id.2.sql, vec!());
+        assert_eq!(field(t, "done").2.default_value, "false");
+        assert_eq!(field(t, "due").2.default_value, "CURRENT_DATE");
+        assert_eq!(field(t, "label").2.default_value, "it's");
+    }
+
+    // pg_dump of what objectdef made for an audited, soft deleted chore in
+    // the api, trimmed to one of each statement. archive is not in the api
+    const GENERATED: &str = "
+        CREATE SCHEMA api;
+        CREATE FUNCTION public.chore_soft_delete() RETURNS trigger
+            LANGUAGE plpgsql SECURITY DEFINER
+            SET search_path TO 'pg_catalog', 'pg_temp'
+            AS $$
+        BEGIN
+          UPDATE public.chore SET deleted_at = now() WHERE id = OLD.id;
+          RETURN OLD;
+        END;
+        $$;
+        CREATE TABLE public.chore (
+            id bigint NOT NULL,
+            name character varying(64) NOT NULL,
+            deleted_at timestamp with time zone
+        );
+        CREATE VIEW api.chore AS
+         SELECT chore.id,
+            chore.name
+           FROM public.chore
+          WHERE (chore.deleted_at IS NULL);
+        CREATE VIEW api.chore_deleted AS
+         SELECT chore.id,
+            chore.name,
+            chore.deleted_at
+           FROM public.chore
+          WHERE (chore.deleted_at IS NOT NULL);
+        CREATE TABLE public.chore_history (
+            id bigint NOT NULL,
+            record_id bigint NOT NULL,
+            operation character varying(6) NOT NULL,
+            changed_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+            changed_by text NOT NULL,
+            old_row jsonb,
+            new_row jsonb
+        );
+        CREATE VIEW api.chore_history AS
+         SELECT chore_history.id,
+            chore_history.record_id
+           FROM public.chore_history;
+        CREATE TABLE public.archive (
+            id bigint NOT NULL,
+            deleted_at timestamp with time zone
+        );
+        CREATE SEQUENCE public.chore_id_seq START WITH 1 INCREMENT BY 1 NO MINVALUE NO MAXVALUE CACHE 1;
+        CREATE SEQUENCE public.chore_history_id_seq START WITH 1 INCREMENT BY 1 NO MINVALUE NO MAXVALUE CACHE 1;
+        ALTER TABLE ONLY public.chore ALTER COLUMN id SET DEFAULT nextval('public.chore_id_seq'::regclass);
+        ALTER TABLE ONLY public.chore_history ALTER COLUMN id SET DEFAULT nextval('public.chore_history_id_seq'::regclass);
+        ALTER TABLE ONLY public.chore ADD CONSTRAINT chore_pkey PRIMARY KEY (id);
+        ALTER TABLE ONLY public.chore_history ADD CONSTRAINT chore_history_pkey PRIMARY KEY (id);
+        ALTER TABLE ONLY public.archive ADD CONSTRAINT archive_pkey PRIMARY KEY (id);
+        CREATE INDEX chore_history_record_id_idx ON public.chore_history USING btree (record_id);
+        CREATE TRIGGER soft_delete INSTEAD OF DELETE ON api.chore FOR EACH ROW EXECUTE FUNCTION public.chore_soft_delete();";
+
+    #[test]
+    fn reads_back_generated_tables_as_options() {
+        let s = build(GENERATED).unwrap();
+        assert_eq!(s.tables.iter().map(|t| t.0.as_str()).collect::<Vec<&str>>(), vec!("chore", "archive"));
+        assert_eq!(s.api, vec!("chore".to_string()));
+
+        let chore = table(&s, "chore");
+        assert!(chore.2.audit && chore.2.soft_delete);
+        assert!(!chore.1.iter().any(|f| f.0 == DELETED_AT));
+
+        // without an api view deleted_at is just a column
+        let archive = table(&s, "archive");
+        assert!(!archive.2.audit && !archive.2.soft_delete);
+        assert_eq!(field(archive, DELETED_AT).2.null, Nullable::Null);
+
+        let out = schema_rson(&s);
+        assert!(out.contains("  ], {audit: true, soft_delete: true}),\n"));
+        let mut written: SchemaSpec = rson_rs::de::from_str(&out).unwrap();
+        written.add_implied_columns();
+        assert_eq!(check_schema(Dialect::Postgres, &written), Ok(()));
+    }
+
+    #[test]
+    fn rejects_what_it_can_not_read() {
+        assert!(build("").is_err());
+        assert!(build("SELECT pg_catalog.set_config('search_path', '', false);").is_err());
+        assert!(build("CREATE TABLE public.chore id bigint;").is_err());
+        // numeric(3,5) is legal from postgres 15, a schema can't hold it
+        let e = build("CREATE TABLE public.chore (price numeric(3,5) DEFAULT 0.001);").unwrap_err();
+        assert!(e.starts_with("chore.price: "));
+    }
+}
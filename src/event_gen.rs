@@ -0,0 +1,329 @@
+use serde_json::{Map, Value};
+
+use schema_spec::*;
+use sql_gen::EVENT_CHANNEL;
+
+
+// A small node program that LISTENs for endpoint events and POSTs each
+// notification, as it came from the trigger, to the url of its event
+const DISPATCHER: &str = r#"// Generated from the schema, edit that instead
+//
+// Listens for endpoint events and POSTs each one to its url:
+//
+//   DATABASE_URL=postgres://... node dispatcher.js
+//
+// Postgres does not keep notifications for a listener that is not there,
+// events from while the dispatcher is down are not sent. Deliveries run side
+// by side, a retried one can land after a later event.
+const { Client } = require('pg');
+
+const CHANNEL = __CHANNEL__;
+
+// "table.event" -> url
+const ROUTES = __ROUTES__;
+
+const ATTEMPTS = Number(process.env.DISPATCH_ATTEMPTS || 5);
+const BACKOFF_MS = Number(process.env.DISPATCH_BACKOFF_MS || 1000);
+const TIMEOUT_MS = Number(process.env.DISPATCH_TIMEOUT_MS || 10000);
+
+const sleep = (ms) => new Promise((resolve) => setTimeout(resolve, ms));
+
+async function post(url, body) {
+  const res = await fetch(url, {
+    method: 'POST',
+    headers: { 'content-type': 'application/json' },
+    body,
+    signal: AbortSignal.timeout(TIMEOUT_MS),
+  });
+  if (!res.ok) {
+    const err = new Error(`${url} answered ${res.status}`);
+    // the same request will get the same answer
+    err.permanent = res.status >= 400 && res.status < 500 && res.status !== 408 && res.status !== 429;
+    throw err;
+  }
+}
+
+// Tries again after 1, 2, 4... backoffs, true once the endpoint took it
+async function deliver(url, body) {
+  for (let attempt = 1; ; attempt++) {
+    try {
+      await post(url, body);
+      return true;
+    } catch (err) {
+      if (err.permanent || attempt >= ATTEMPTS) {
+        console.error(`giving up on ${url} after ${attempt} attempt(s): ${err.message}`);
+        return false;
+      }
+      await sleep(BACKOFF_MS * 2 ** (attempt - 1));
+    }
+  }
+}
+
+async function dispatch(payload) {
+  let event;
+  try {
+    event = JSON.parse(payload);
+  } catch (err) {
+    console.error(`not an event: ${payload}`);
+    return false;
+  }
+  const url = ROUTES[`${event.table}.${event.event}`];
+  if (!url) {
+    console.error(`no endpoint for ${event.table}.${event.event}`);
+    return false;
+  }
+  return deliver(url, payload);
+}
+
+// Resolves when the connection goes away
+async function listen() {
+  const client = new Client({ connectionString: process.env.DATABASE_URL });
+  const closed = new Promise((resolve) => {
+    client.on('error', (err) => {
+      console.error(`connection failed: ${err.message}`);
+      resolve();
+    });
+    client.on('end', resolve);
+  });
+  client.on('notification', (msg) => {
+    if (msg.channel === CHANNEL) {
+      dispatch(msg.payload);
+    }
+  });
+  await client.connect();
+  await client.query(`LISTEN ${CHANNEL}`);
+  await closed;
+  client.end().catch(() => {});
+}
+
+async function main() {
+  for (;;) {
+    try {
+      await listen();
+    } catch (err) {
+      console.error(`could not listen: ${err.message}`);
+    }
+    await sleep(BACKOFF_MS);
+  }
+}
+
+module.exports = { dispatch, deliver, ROUTES };
+
+if (require.main === module) {
+  main();
+}
+"#;
+
+const PACKAGE: &str = r#"{
+  "name": "event-dispatcher",
+  "private": true,
+  "main": "dispatcher.js",
+  "engines": { "node": ">=18" },
+  "scripts": { "start": "node dispatcher.js" },
+  "dependencies": { "pg": "^8.11.0" }
+}
+"#;
+
+
+pub fn endpoint_events(s: &SchemaSpec) -> Vec<(&EventSpec, &str)> {
+    s.events.iter().filter_map(|e| {
+        match e.event_location {
+            LocationDef::Endpoint { ref url } => Some((e, url.as_str())),
+            _ => None
+        }
+    }).collect()
+}
+
+pub fn dispatcher_js(s: &SchemaSpec) -> String {
+    let mut routes = Map::new();
+    for (e, url) in endpoint_events(s) {
+        routes.insert(format!("{}.{}", e.table, e.event_name), Value::from(url));
+    }
+    DISPATCHER
+        .replace("__CHANNEL__", &Value::from(EVENT_CHANNEL).to_string())
+        .replace("__ROUTES__", &serde_json::to_string_pretty(&routes).unwrap())
+}
+
+pub fn dispatcher_package() -> String {
+    PACKAGE.into()
+}
+
+
+// The data provider wrapper the frontend events are run from, it sees every
+// write whichever view or bulk action makes it
+const HOOKS: &str = r#"
+const hooksFor = (resource, op, timing) =>
+  (HOOKS[resource] || []).filter((h) => h.timing === timing && h.on.includes(op));
+
+const hasHooks = (resource, op) =>
+  hooksFor(resource, op, 'before').length + hooksFor(resource, op, 'after').length > 0;
+
+// A before hook can hand back the record to write instead, or throw to stop it
+async function before(resource, op, record, previous) {
+  for (const h of hooksFor(resource, op, 'before')) {
+    const out = await h.run(record, previous, op);
+    if (out !== undefined) {
+      record = out;
+    }
+  }
+  return record;
+}
+
+// The write already happened, a failing after hook does not undo it
+async function after(resource, op, record, previous) {
+  for (const h of hooksFor(resource, op, 'after')) {
+    try {
+      await h.run(record, previous, op);
+    } catch (err) {
+      console.error(`${resource} ${h.run.name}: ${err.message}`);
+    }
+  }
+}
+
+// react-admin 2 data providers are one function, (type, resource, params)
+export const withHooks = (dataProvider) => {
+  const hooked = async (type, resource, params) => {
+    switch (type) {
+      case CREATE: {
+        const data = await before(resource, 'create', params.data, undefined);
+        const result = await dataProvider(type, resource, { ...params, data });
+        await after(resource, 'create', result.data, undefined);
+        return result;
+      }
+      case UPDATE: {
+        const data = await before(resource, 'update', params.data, params.previousData);
+        const result = await dataProvider(type, resource, { ...params, data });
+        await after(resource, 'update', result.data, params.previousData);
+        return result;
+      }
+      case DELETE: {
+        const previous = params.previousData || { id: params.id };
+        await before(resource, 'delete', previous, previous);
+        const result = await dataProvider(type, resource, params);
+        await after(resource, 'delete', result.data || previous, previous);
+        return result;
+      }
+      // a bulk write goes one record at a time when there is a hook to run,
+      // each fetched first so its hooks see what it was
+      case UPDATE_MANY: {
+        if (!hasHooks(resource, 'update')) {
+          return dataProvider(type, resource, params);
+        }
+        const { data: records } = await dataProvider(GET_MANY, resource, { ids: params.ids });
+        await Promise.all(records.map((previousData) => hooked(UPDATE, resource, {
+          id: previousData.id, data: { ...previousData, ...params.data, id: previousData.id }, previousData,
+        })));
+        return { data: records.map((r) => r.id) };
+      }
+      case DELETE_MANY: {
+        if (!hasHooks(resource, 'delete')) {
+          return dataProvider(type, resource, params);
+        }
+        const { data: records } = await dataProvider(GET_MANY, resource, { ids: params.ids });
+        await Promise.all(records.map((previousData) => hooked(DELETE, resource, { id: previousData.id, previousData })));
+        return { data: records.map((r) => r.id) };
+      }
+      default:
+        return dataProvider(type, resource, params);
+    }
+  };
+  return hooked;
+};
+"#;
+
+
+fn hook_op(k: &EventKind) -> &'static str {
+    match k {
+        EventKind::Insert => "create",
+        EventKind::Update => "update",
+        EventKind::Delete => "delete",
+    }
+}
+
+pub fn frontend_events(s: &SchemaSpec) -> Vec<&EventSpec> {
+    s.events.iter().filter(|e| e.event_location == LocationDef::Frontend).collect()
+}
+
+// Each event becomes function <table>_<event>(record, previous, op) with its
+// source as the body, record is what is written or what came back from the
+// write and previous the record before an update or delete
+pub fn hooks_js(s: &SchemaSpec) -> String {
+    let events = frontend_events(s);
+
+    let functions = events.iter().map(|e| {
+        format!("export function {}_{}(record, previous, op) {{\n  {}\n}}\n",
+                e.table, e.event_name, e.source.trim().replace("\n", "\n  "))
+    }).collect::<Vec<String>>().join("\n");
+
+    let mut tables: Vec<&str> = vec!();
+    for e in events.iter() {
+        if !tables.contains(&e.table.as_str()) {
+            tables.push(&e.table);
+        }
+    }
+    let entries = tables.iter().map(|t| {
+        let hooks = events.iter().filter(|e| e.table == *t).map(|e| {
+            let on = e.on.iter().map(|k| format!("'{}'", hook_op(k))).collect::<Vec<String>>().join(", ");
+            let timing = match e.timing {
+                EventTiming::Before => "before",
+                EventTiming::After => "after",
+            };
+            format!("    {{ on: [{}], timing: '{}', run: {}_{} }},", on, timing, e.table, e.event_name)
+        }).collect::<Vec<String>>().join("\n");
+        format!("  {}: [\n{}\n  ],", t, hooks)
+    }).collect::<Vec<String>>().join("\n");
+
+    format!("// Generated from the schema, edit that instead\n\
+             import {{ GET_MANY, CREATE, UPDATE, DELETE, UPDATE_MANY, DELETE_MANY }} from 'react-admin';\n\n\
+             {}\nconst HOOKS = {{\n{}\n}};\n{}",
+            functions, entries, HOOKS)
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate rson_rs;
+
+    use self::rson_rs::de::from_str;
+    use super::*;
+    use sql_gen::event_sql;
+
+    const SCHEMA: &str = r#"{ tables: [
+        ("chore", [("id", BigSerialPk, {}), ("name", String(64), {})]),
+      ],
+      views: [], relationships: [], api: [], acl: [],
+      events: [
+        { event_name: "done", table: "chore", on: [Update], timing: After,
+          event_location: Endpoint { url: "http://localhost:9000/done" } }
+      ] }"#;
+
+    #[test]
+    fn endpoint_events_notify_the_dispatcher() {
+        let s: SchemaSpec = from_str(SCHEMA).unwrap();
+        let (create, _) = event_sql(&s, &s.events[0]);
+        assert!(create.contains("payload := json_build_object('event', 'done', 'table', 'chore', 'op', TG_OP, \
+                                 'old', row_to_json(OLD), 'new', row_to_json(NEW))::text;\n  \
+                                 IF octet_length(payload) >= 8000 THEN\n    \
+                                 payload := json_build_object('event', 'done', 'table', 'chore', 'op', TG_OP, 'truncated', true)::text;\n  \
+                                 END IF;\n  \
+                                 PERFORM pg_notify('appgen_events', payload);\n  \
+                                 RETURN NULL;\nEND;"));
+
+        let js = dispatcher_js(&s);
+        assert!(js.contains("const CHANNEL = \"appgen_events\";"));
+        assert!(js.contains("\"chore.done\": \"http://localhost:9000/done\""));
+        assert!(!js.contains("__"));
+    }
+
+    #[test]
+    fn bulk_writes_hand_each_hook_the_record_before() {
+        let s: SchemaSpec = from_str(&SCHEMA.replace(
+            r#"event_location: Endpoint { url: "http://localhost:9000/done" }"#,
+            r#"event_location: Frontend, source: "return record;""#)).unwrap();
+        let js = hooks_js(&s);
+        assert!(js.contains("import { GET_MANY, CREATE, UPDATE, DELETE, UPDATE_MANY, DELETE_MANY } from 'react-admin';"));
+        assert!(js.contains("export function chore_done(record, previous, op) {\n  return record;\n}"));
+        assert_eq!(js.matches("await dataProvider(GET_MANY, resource, { ids: params.ids });").count(), 2);
+        assert!(js.contains("hooked(DELETE, resource, { id: previousData.id, previousData })"));
+    }
+}
@@ -1,5 +1,8 @@
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 pub mod schema_spec;
 pub mod sql_gen;
 pub mod view_gen;
+pub mod migration;
+pub mod event_gen;
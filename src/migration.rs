@@ -0,0 +1,590 @@
+use schema_spec::*;
+use sql_gen::*;
+
+
+// Migrations are only generated for postgres.
+// Ordered so that constraints are dropped before the columns/tables they
+// touch and only re-added once everything they reference exists.
+// Tables are held by their qualified name, drops run before a table moves
+// namespace and take the old one
+#[derive(Debug)]
+pub enum Migration<'a> {
+    DropView(String),
+    DropFunction(String),
+    RevokeAcl(Vec<String>),
+    DropEvent(String),
+    DropForeignKey(String, &'a Relation),
+    DropPrimaryKey(String, &'a String),
+    DropConstraint(String, String),
+    DropIndex(String),
+    DropTable(String),
+    DropColumn(String, &'a Field),
+    CreateSchema(&'a str),
+    CreateRole(&'a str),
+    SetSchema(String, &'a str),
+    CreateTable(&'a str, &'a Table),
+    CreateJoinTable(Table),
+    AddColumn(String, &'a Field),
+    DropExpression(String, &'a Field),
+    AlterColumnType(String, &'a Field),
+    AlterColumnNull(String, &'a Field),
+    AlterColumnDefault(String, &'a Field),
+    AddPrimaryKey(String, Vec<&'a Field>),
+    AddConstraint(String, String),
+    CreateIndex(String),
+    AddForeignKey(String),
+    CreateEvent(String),
+    GrantAcl(Vec<String>),
+    ApiSetup,
+    CreateView(String),
+}
+
+impl<'a> Migration<'a> {
+    fn phase(&self) -> usize {
+        match self {
+            Migration::DropView(_) => 0,
+            Migration::DropFunction(_) => 0,
+            Migration::RevokeAcl(_) => 1,
+            Migration::DropEvent(_) => 2,
+            Migration::DropForeignKey(_, _) => 3,
+            Migration::DropPrimaryKey(_, _) => 4,
+            Migration::DropConstraint(_, _) => 5,
+            Migration::DropIndex(_) => 6,
+            Migration::DropTable(_) => 7,
+            Migration::DropColumn(_, _) => 8,
+            Migration::CreateSchema(_) => 9,
+            Migration::CreateRole(_) => 10,
+            Migration::SetSchema(_, _) => 11,
+            Migration::CreateTable(_, _) => 12,
+            Migration::CreateJoinTable(_) => 12,
+            Migration::AddColumn(_, _) => 13,
+            Migration::DropExpression(_, _) => 14,
+            Migration::AlterColumnType(_, _) => 14,
+            Migration::AlterColumnNull(_, _) => 15,
+            Migration::AlterColumnDefault(_, _) => 16,
+            Migration::AddPrimaryKey(_, _) => 17,
+            Migration::AddConstraint(_, _) => 18,
+            Migration::CreateIndex(_) => 19,
+            Migration::AddForeignKey(_) => 20,
+            Migration::CreateEvent(_) => 21,
+            Migration::GrantAcl(_) => 22,
+            Migration::ApiSetup => 23,
+            Migration::CreateView(_) => 24,
+        }
+    }
+}
+
+
+fn find_table<'a>(s: &'a SchemaSpec, name: &str) -> Option<&'a Table> {
+    s.tables.iter().find(|t| t.0 == name)
+}
+
+// Many-to-many relations that only differ in their actions share a join
+// table, its rows stay and only the keys on it are redone
+fn same_join_table(a: &Relation, b: &Relation) -> bool {
+    a.is_many_to_many() && b.is_many_to_many() && a.from == b.from && a.to == b.to
+}
+
+// A NOT NULL column added to a table that may already hold rows, it needs
+// a value for them before the constraint can go on. Serial and computed
+// columns fill themselves
+fn needs_fill(f: &Field) -> bool {
+    f.2.null == Nullable::NotNull && !f.is_computed() && f.1 != MappedFieldType::BigSerialPk
+}
+
+fn diff_fields<'a>(old_spec: &'a SchemaSpec, new_spec: &'a SchemaSpec, old: &'a Table, new: &'a Table, out: &mut Vec<Migration<'a>>) {
+    let namespace = new_spec.namespace_of(&new.0);
+    let old_table = table_ref(Dialect::Postgres, old_spec, &old.0);
+    let table = table_ref(Dialect::Postgres, new_spec, &new.0);
+    // columns created from scratch, a serial one brings its primary key along
+    let mut added = vec!();
+
+    for f in old.1.iter().filter(|f| !new.1.iter().any(|n| n.0 == f.0)) {
+        out.push(Migration::DropColumn(old_table.clone(), f));
+    }
+
+    for f in new.1.iter() {
+        match old.1.iter().find(|o| o.0 == f.0) {
+            None => {
+                out.push(Migration::AddColumn(table.clone(), f));
+                added.push(&f.0);
+            }
+            Some(o) => {
+                // an expression can't be altered, a plain column keeps the
+                // values it was computed to
+                if f.is_computed() && o.2.computed != f.2.computed {
+                    out.push(Migration::DropColumn(old_table.clone(), o));
+                    out.push(Migration::AddColumn(table.clone(), f));
+                    added.push(&f.0);
+                    continue;
+                }
+                if o.is_computed() && !f.is_computed() {
+                    out.push(Migration::DropExpression(table.clone(), f));
+                }
+
+                match (&o.1, &f.1) {
+                    _ if column_type(Dialect::Postgres, o) == column_type(Dialect::Postgres, f) => {}
+                    // Serial columns carry a sequence, there is no sane ALTER for them
+                    (MappedFieldType::BigSerialPk, _) |
+                    (_, MappedFieldType::BigSerialPk) => {
+                        out.push(Migration::DropColumn(old_table.clone(), o));
+                        out.push(Migration::AddColumn(table.clone(), f));
+                        added.push(&f.0);
+                        continue;
+                    }
+                    _ => {
+                        out.push(Migration::AlterColumnType(table.clone(), f));
+                    }
+                }
+
+                if o.2.null != f.2.null {
+                    out.push(Migration::AlterColumnNull(table.clone(), f));
+                }
+
+                if o.2.default_value != f.2.default_value {
+                    out.push(Migration::AlterColumnDefault(table.clone(), f));
+                }
+            }
+        }
+    }
+
+    let old_pk: Vec<&Field> = old.1.iter().filter(|f| f.is_primary_key()).collect();
+    let new_pk: Vec<&Field> = new.1.iter().filter(|f| f.is_primary_key()).collect();
+
+    if old_pk.iter().map(|f| &f.0).ne(new_pk.iter().map(|f| &f.0)) {
+        // postgres drops the constraint along with any of its columns
+        if old_pk.len() > 0 && old_pk.iter().all(|f| new.1.iter().any(|n| n.0 == f.0) && !added.contains(&&f.0)) {
+            out.push(Migration::DropPrimaryKey(old_table.clone(), &old.0));
+        }
+        if new_pk.len() > 0 && !new_pk.iter().any(|f| f.1 == MappedFieldType::BigSerialPk && added.contains(&&f.0)) {
+            out.push(Migration::AddPrimaryKey(table.clone(), new_pk));
+        }
+    }
+
+    let old_constraints = table_constraints(Dialect::Postgres, old);
+    let new_constraints = table_constraints(Dialect::Postgres, new);
+    for c in old_constraints.iter().filter(|c| !new_constraints.contains(c)) {
+        out.push(Migration::DropConstraint(old_table.clone(), c.0.clone()));
+    }
+    for c in new_constraints.into_iter().filter(|c| !old_constraints.contains(c)) {
+        out.push(Migration::AddConstraint(table.clone(), c.1));
+    }
+
+    // compared in the new namespace, moving a table keeps its indexes
+    let old_indexes = table_indexes(Dialect::Postgres, namespace, old);
+    let new_indexes = table_indexes(Dialect::Postgres, namespace, new);
+    for i in old_indexes.iter().filter(|i| !new_indexes.contains(i)) {
+        out.push(Migration::DropIndex(qualify(Dialect::Postgres, old_spec.namespace_of(&old.0), &i.0)));
+    }
+    for i in new_indexes.into_iter().filter(|i| !old_indexes.contains(i)) {
+        out.push(Migration::CreateIndex(i.1));
+    }
+}
+
+
+pub fn diff_schema<'a>(old: &'a SchemaSpec, new: &'a SchemaSpec) -> Vec<Migration<'a>> {
+    let mut out = vec!();
+
+    for r in old.relationships.iter().filter(|r| !new.relationships.contains(r)) {
+        if r.is_many_to_many() && !new.relationships.iter().any(|n| same_join_table(r, n)) {
+            // the keys go along with the join table
+            out.push(Migration::DropTable(table_ref(Dialect::Postgres, old, &r.join_table_name())));
+            continue;
+        }
+        let t = if r.is_many_to_many() { r.join_table_name() } else { r.from.table().clone() };
+        out.push(Migration::DropForeignKey(table_ref(Dialect::Postgres, old, &t), r));
+    }
+
+    for t in old.tables.iter().filter(|t| find_table(new, &t.0).is_none()) {
+        out.push(Migration::DropTable(table_ref(Dialect::Postgres, old, &t.0)));
+    }
+
+    let old_namespaces = old.namespaces();
+    for n in new.namespaces().into_iter().filter(|n| !old_namespaces.contains(n)) {
+        out.push(Migration::CreateSchema(n));
+    }
+
+    let old_roles = old.roles();
+    for r in new.roles().into_iter().filter(|r| !old_roles.contains(r)) {
+        out.push(Migration::CreateRole(r));
+    }
+
+    // kept tables and join tables that changed namespace
+    let mut kept = old.tables.iter()
+        .filter(|t| find_table(new, &t.0).is_some())
+        .map(|t| t.0.clone())
+        .collect::<Vec<String>>();
+    kept.extend(old.relationships.iter()
+                .filter(|r| new.relationships.iter().any(|n| same_join_table(r, n)))
+                .map(|r| r.join_table_name()));
+    kept.extend(old.tables.iter()
+                .filter(|t| t.2.audit && find_table(new, &t.0).map(|n| n.2.audit).unwrap_or(false))
+                .map(|t| t.history_table_name()));
+    for t in kept.iter().filter(|t| old.namespace_of(t) != new.namespace_of(t)) {
+        out.push(Migration::SetSchema(table_ref(Dialect::Postgres, old, t), new.namespace_of(t)));
+    }
+
+    // tables an api view has to be rebuilt over
+    let mut changed = vec!();
+    for t in new.tables.iter() {
+        match find_table(old, &t.0) {
+            Some(o) => {
+                let before = out.len();
+                diff_fields(old, new, o, t, &mut out);
+                if out.len() > before {
+                    changed.push(qualify(Dialect::Postgres, API_NAMESPACE, &t.0));
+                }
+            }
+            None => out.push(Migration::CreateTable(new.namespace_of(&t.0), t))
+        }
+
+        // the whole acl of a table is redone when any of it changes
+        let (grant, _) = table_acl(new, t);
+        let (old_grant, revoke) = find_table(old, &t.0).map(|o| table_acl(old, o)).unwrap_or_default();
+        if grant != old_grant {
+            if revoke.len() > 0 {
+                out.push(Migration::RevokeAcl(revoke));
+            }
+            if grant.len() > 0 {
+                out.push(Migration::GrantAcl(grant));
+            }
+        }
+    }
+
+    for r in new.relationships.iter().filter(|r| !old.relationships.contains(r)) {
+        if !old.relationships.iter().any(|o| same_join_table(o, r)) {
+            if let Some(j) = join_table(new, r) {
+                out.push(Migration::CreateJoinTable(j));
+            }
+        }
+        out.push(Migration::AddForeignKey(add_foreign_keys(Dialect::Postgres, new, r)));
+    }
+
+    // a trigger is replaced whenever anything in it changes. One on a
+    // dropped table goes too, the table takes the trigger but leaves the
+    // function behind
+    let old_events = database_events(old).into_iter().map(|e| event_sql(old, e)).collect::<Vec<(String, String)>>();
+    let new_events = database_events(new).into_iter().map(|e| event_sql(new, e)).collect::<Vec<(String, String)>>();
+    for sql in old_events.iter().filter(|e| !new_events.contains(e)) {
+        out.push(Migration::DropEvent(sql.1.clone()));
+    }
+    for sql in new_events.iter().filter(|e| !old_events.contains(e)) {
+        out.push(Migration::CreateEvent(sql.0.clone()));
+    }
+
+    // audit triggers go the same way, their history tables are kept
+    for t in old.tables.iter() {
+        if let Some(sql) = audit_sql(old, t) {
+            if Some(&sql) != find_table(new, &t.0).and_then(|n| audit_sql(new, n)).as_ref() {
+                out.push(Migration::DropEvent(sql.1));
+            }
+        }
+    }
+    for t in new.tables.iter() {
+        if let Some(sql) = audit_sql(new, t) {
+            if Some(&sql) != find_table(old, &t.0).and_then(|o| audit_sql(old, o)).as_ref() {
+                out.push(Migration::CreateEvent(sql.0));
+            }
+        }
+    }
+
+    // views pin the columns under them, so they come down first and go
+    // back up once the tables are done
+    let old_views = api_views(old);
+    let new_views = api_views(new);
+    let rebuild = |v: &(String, String)| {
+        !old_views.contains(v) || !new_views.contains(v) || changed.contains(&v.0)
+    };
+    for v in old_views.iter().filter(|v| rebuild(v)) {
+        out.push(Migration::DropView(v.0.clone()));
+    }
+    let new_functions = api_functions(new);
+    for f in api_functions(old).into_iter().filter(|f| !new_functions.contains(f)) {
+        out.push(Migration::DropFunction(f));
+    }
+    if old.api.len() == 0 && new.api.len() > 0 {
+        out.push(Migration::ApiSetup);
+    }
+    for v in new_views.iter().filter(|v| rebuild(v)) {
+        out.push(Migration::CreateView(v.1.clone()));
+    }
+
+    // stable, so declaration order is kept within a phase
+    out.sort_by_key(|m| m.phase());
+    out
+}
+
+// Catches what diff_schema can't write sql for
+pub fn check_migration(old: &SchemaSpec, new: &SchemaSpec) -> Result<(), String> {
+    for m in diff_schema(old, new).iter() {
+        if let Migration::AddColumn(t, f) = m {
+            if needs_fill(f) && f.2.default_value.len() == 0 {
+                return Err(format!("migration: {}.{} is added as not null without a default to fill the existing rows with", t, f.0));
+            }
+        }
+    }
+    Ok(())
+}
+
+
+// Migrations are postgres only, so are their identifiers
+fn ident(name: &str) -> String {
+    quote(Dialect::Postgres, name)
+}
+
+impl<'a, 'b> From<&'b Migration<'a>> for CreateSql {
+    fn from(m: &Migration) -> CreateSql {
+        match m {
+            Migration::DropView(v) => {
+                format!("DROP VIEW IF EXISTS {};", v).into()
+            }
+            Migration::DropFunction(f) => {
+                format!("DROP FUNCTION IF EXISTS {}();", f).into()
+            }
+            Migration::DropEvent(sql) |
+            Migration::CreateEvent(sql) => {
+                sql.clone().into()
+            }
+            Migration::RevokeAcl(sql) |
+            Migration::GrantAcl(sql) => {
+                sql.join("\n").into()
+            }
+            Migration::CreateRole(r) => {
+                create_role(r).into()
+            }
+            Migration::DropForeignKey(t, r) if r.is_many_to_many() => {
+                let (from, to) = r.join_constraint_names();
+                format!("ALTER TABLE {} DROP CONSTRAINT {}, DROP CONSTRAINT {};", t, ident(&from), ident(&to)).into()
+            }
+            Migration::DropForeignKey(t, r) => {
+                format!("ALTER TABLE {} DROP CONSTRAINT {};", t, ident(&r.constraint_name())).into()
+            }
+            Migration::DropPrimaryKey(t, name) => {
+                format!("ALTER TABLE {} DROP CONSTRAINT {};", t, ident(&format!("{}_pkey", name))).into()
+            }
+            // these may already have gone along with a dropped column
+            Migration::DropConstraint(t, name) => {
+                format!("ALTER TABLE {} DROP CONSTRAINT IF EXISTS {};", t, ident(name)).into()
+            }
+            Migration::DropIndex(name) => {
+                format!("DROP INDEX IF EXISTS {};", name).into()
+            }
+            Migration::DropTable(t) => {
+                format!("DROP TABLE {};", t).into()
+            }
+            Migration::DropColumn(t, f) => {
+                format!("ALTER TABLE {} DROP COLUMN {};", t, ident(&f.0)).into()
+            }
+            Migration::CreateSchema(n) => {
+                format!("CREATE SCHEMA IF NOT EXISTS {};", ident(n)).into()
+            }
+            // leaving every namespace means going back to the default one
+            Migration::SetSchema(t, n) => {
+                let n = if n.len() > 0 { *n } else { "public" };
+                format!("ALTER TABLE {} SET SCHEMA {};", t, ident(n)).into()
+            }
+            Migration::CreateTable(n, t) => {
+                create_table(Dialect::Postgres, n, t, vec!()).into()
+            }
+            Migration::CreateJoinTable(t) => {
+                CreateSql::from(t)
+            }
+            // added without NOT NULL so the existing rows take the default,
+            // check_migration has made sure there is one
+            Migration::AddColumn(t, f) if needs_fill(f) => {
+                let lit = sql_literal(Dialect::Postgres, &f.1, &f.2.default_value).expect("default checked by check_schema");
+                format!("ALTER TABLE {} ADD COLUMN {} {} DEFAULT {};\nALTER TABLE {} ALTER COLUMN {} SET NOT NULL;",
+                        t, ident(&f.0), column_type(Dialect::Postgres, f), lit, t, ident(&f.0)).into()
+            }
+            Migration::AddColumn(t, f) => {
+                format!("ALTER TABLE {} ADD COLUMN {};", t, gen_create_sql(*f)).into()
+            }
+            Migration::AlterColumnType(t, f) => {
+                let ty = column_type(Dialect::Postgres, f);
+                format!("ALTER TABLE {} ALTER COLUMN {} TYPE {} USING {}::{};", t, ident(&f.0), ty, ident(&f.0), ty).into()
+            }
+            Migration::DropExpression(t, f) => {
+                format!("ALTER TABLE {} ALTER COLUMN {} DROP EXPRESSION;", t, ident(&f.0)).into()
+            }
+            Migration::AlterColumnNull(t, f) => {
+                match f.2.null {
+                    Nullable::NotNull => format!("ALTER TABLE {} ALTER COLUMN {} SET NOT NULL;", t, ident(&f.0)).into(),
+                    Nullable::Null => format!("ALTER TABLE {} ALTER COLUMN {} DROP NOT NULL;", t, ident(&f.0)).into()
+                }
+            }
+            Migration::AlterColumnDefault(t, f) => {
+                match sql_literal(Dialect::Postgres, &f.1, &f.2.default_value) {
+                    Ok(lit) if f.2.default_value.len() > 0 => {
+                        format!("ALTER TABLE {} ALTER COLUMN {} SET DEFAULT {};", t, ident(&f.0), lit).into()
+                    }
+                    _ => format!("ALTER TABLE {} ALTER COLUMN {} DROP DEFAULT;", t, ident(&f.0)).into()
+                }
+            }
+            Migration::AddPrimaryKey(t, fields) => {
+                let cols = fields.iter().map(|f| ident(&f.0)).collect::<Vec<String>>().join(", ");
+                format!("ALTER TABLE {} ADD PRIMARY KEY ({});", t, cols).into()
+            }
+            Migration::AddConstraint(t, sql) => {
+                format!("ALTER TABLE {} ADD {};", t, sql).into()
+            }
+            Migration::CreateIndex(sql) => {
+                sql.clone().into()
+            }
+            Migration::AddForeignKey(sql) => {
+                sql.clone().into()
+            }
+            Migration::ApiSetup => {
+                api_setup().join("\n").into()
+            }
+            Migration::CreateView(sql) => {
+                sql.clone().into()
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate rson_rs;
+
+    use self::rson_rs::de::from_str;
+    use super::*;
+
+    const KEPT: &str = r#"{ tables: [
+        ("chore", [("id", BigSerialPk, {}), ("name", String(64), {})]),
+      ],
+      views: [], relationships: [], api: [], acl: [] }"#;
+
+    // the same, plus an audited table with an event of its own
+    const WITH_KID: &str = r#"{ tables: [
+        ("chore", [("id", BigSerialPk, {}), ("name", String(64), {})]),
+        ("kid", [("id", BigSerialPk, {}), ("name", String(64), {})], {audit: true}),
+      ],
+      views: [], relationships: [], api: [], acl: [],
+      events: [
+        { event_name: "shout", table: "kid", on: [Insert], timing: Before, source: "NEW.name := upper(NEW.name);" }
+      ] }"#;
+
+    fn migrate(old: &SchemaSpec, new: &SchemaSpec) -> Vec<String> {
+        diff_schema(old, new).iter().map(|m| String::from(CreateSql::from(m))).collect()
+    }
+
+    #[test]
+    fn dropping_an_audited_table_drops_its_functions() {
+        let kept: SchemaSpec = from_str(KEPT).unwrap();
+        let with_kid: SchemaSpec = from_str(WITH_KID).unwrap();
+
+        let down = migrate(&with_kid, &kept);
+        let position = |s: &str| down.iter().position(|m| m.contains(s));
+        let audit = position("DROP FUNCTION IF EXISTS kid_audit();").expect("audit function dropped");
+        let shout = position("DROP FUNCTION IF EXISTS kid_shout();").expect("event function dropped");
+        let table = position("DROP TABLE kid;").expect("table dropped");
+        assert!(audit < table && shout < table);
+
+        let up = migrate(&kept, &with_kid);
+        assert!(up.iter().any(|m| m.contains("CREATE TABLE kid (")));
+        assert!(up.iter().any(|m| m.contains("CREATE FUNCTION kid_audit()")));
+        assert!(up.iter().any(|m| m.contains("CREATE FUNCTION kid_shout()")));
+    }
+
+    const FAMILY: &str = r#"{ tables: [
+        ("kid", [("id", BigSerialPk, {}), ("name", String(64), {})]),
+        ("chore", [("id", BigSerialPk, {}), ("name", String(64), {}), ("note", Text, {null: Null}), ("kid_id", Integer, {})]),
+        ("pet", [("id", BigSerialPk, {}), ("name", String(64), {})]),
+      ],
+      views: [], api: [], acl: [],
+      relationships: [
+        { from: Many("chore", "kid_id"), to: One("kid", "id") },
+      ] }"#;
+
+    // pet goes, toy comes, chore changes a column of each kind and its key
+    // starts cascading
+    const REARRANGED: &str = r#"{ tables: [
+        ("kid", [("id", BigSerialPk, {}), ("name", String(64), {})]),
+        ("chore", [("id", BigSerialPk, {}), ("name", Text, {}), ("kid_id", Integer, {}), ("done", Boolean, {default: "false"})]),
+        ("toy", [("id", BigSerialPk, {}), ("kid_id", Integer, {})]),
+      ],
+      views: [], api: [], acl: [],
+      relationships: [
+        { from: Many("chore", "kid_id"), to: One("kid", "id"), on_delete: Cascade },
+        { from: Many("toy", "kid_id"), to: One("kid", "id") },
+      ] }"#;
+
+    #[test]
+    fn tables_columns_and_keys_change_in_order() {
+        let family: SchemaSpec = from_str(FAMILY).unwrap();
+        let rearranged: SchemaSpec = from_str(REARRANGED).unwrap();
+
+        assert_eq!(migrate(&family, &rearranged), vec!(
+            "ALTER TABLE chore DROP CONSTRAINT chore_kid_id_fkey;",
+            "DROP TABLE pet;",
+            "ALTER TABLE chore DROP COLUMN note;",
+            "CREATE TABLE toy (\n  id BigSerial Primary Key,\n  kid_id BigInt NOT NULL\n);",
+            "ALTER TABLE chore ADD COLUMN done Boolean DEFAULT FALSE;\nALTER TABLE chore ALTER COLUMN done SET NOT NULL;",
+            "ALTER TABLE chore ALTER COLUMN name TYPE Text USING name::Text;",
+            "ALTER TABLE chore ADD FOREIGN KEY (kid_id) REFERENCES kid(id) ON DELETE CASCADE;\n",
+            "ALTER TABLE toy ADD FOREIGN KEY (kid_id) REFERENCES kid(id);\n"));
+
+        // and back, the foreign keys come off before the tables they are on
+        assert!(check_migration(&rearranged, &family).is_ok());
+        let down = migrate(&rearranged, &family);
+        let position = |s: &str| down.iter().position(|m| m.contains(s)).expect(s);
+        assert!(position("ALTER TABLE toy DROP CONSTRAINT toy_kid_id_fkey;") < position("DROP TABLE toy;"));
+        assert!(position("DROP TABLE toy;") < position("CREATE TABLE pet ("));
+        assert!(position("ALTER TABLE chore DROP COLUMN done;") < position("ALTER TABLE chore ADD COLUMN note Text;"));
+        assert!(position("ALTER TABLE chore ALTER COLUMN name TYPE varchar(64)") < position("ALTER TABLE chore ADD FOREIGN KEY (kid_id) REFERENCES kid(id);"));
+    }
+
+    const TAGGED: &str = r#"{ tables: [
+        ("chore", [("id", BigSerialPk, {}), ("name", String(64), {})]),
+        ("tag", [("id", BigSerialPk, {}), ("name", String(64), {})]),
+      ],
+      views: [], api: ["chore", "tag"], acl: [],
+      relationships: [
+        { from: Many("chore", "id"), to: Many("tag", "id") },
+      ] }"#;
+
+    #[test]
+    fn changing_many_to_many_actions_keeps_the_join_table() {
+        let old: SchemaSpec = from_str(TAGGED).unwrap();
+        let new: SchemaSpec = from_str(&TAGGED.replace(
+            r#"to: Many("tag", "id") }"#,
+            r#"to: Many("tag", "id"), on_delete: Cascade }"#)).unwrap();
+
+        let up = migrate(&old, &new);
+        assert!(!up.iter().any(|m| m.contains("DROP TABLE") || m.contains("CREATE TABLE")));
+        let drop = up.iter().position(|m| m.contains(
+            "ALTER TABLE chore_tag DROP CONSTRAINT chore_tag_chore_id_fkey, DROP CONSTRAINT chore_tag_tag_id_fkey;")).expect("keys dropped");
+        let add = up.iter().position(|m| m.contains(
+            "ALTER TABLE chore_tag ADD FOREIGN KEY (tag_id) REFERENCES tag(id) ON DELETE CASCADE;")).expect("keys added");
+        assert!(drop < add);
+
+        // without the relation the join table goes
+        let unrelated: SchemaSpec = from_str(&TAGGED.replace(
+            r#"{ from: Many("chore", "id"), to: Many("tag", "id") },"#, "")).unwrap();
+        assert!(migrate(&old, &unrelated).contains(&"DROP TABLE chore_tag;".to_string()));
+    }
+
+    #[test]
+    fn a_new_not_null_column_is_filled_from_its_default() {
+        let kept: SchemaSpec = from_str(KEPT).unwrap();
+        let with_points: SchemaSpec = from_str(&KEPT.replace(
+            r#"("name", String(64), {})"#,
+            r#"("name", String(64), {}), ("points", Integer, {default: "0"})"#)).unwrap();
+
+        assert_eq!(migrate(&kept, &with_points), vec!(
+            "ALTER TABLE chore ADD COLUMN points BigInt DEFAULT 0;\nALTER TABLE chore ALTER COLUMN points SET NOT NULL;"));
+        assert!(check_migration(&kept, &with_points).is_ok());
+
+        let without_default: SchemaSpec = from_str(&KEPT.replace(
+            r#"("name", String(64), {})"#,
+            r#"("name", String(64), {}), ("points", Integer, {})"#)).unwrap();
+        assert!(check_migration(&kept, &without_default).unwrap_err().contains("chore.points"));
+
+        let nullable: SchemaSpec = from_str(&KEPT.replace(
+            r#"("name", String(64), {})"#,
+            r#"("name", String(64), {}), ("points", Integer, {null: Null})"#)).unwrap();
+        assert!(check_migration(&kept, &nullable).is_ok());
+    }
+}
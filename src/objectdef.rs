@@ -20,6 +20,8 @@ use std::rc::Rc;
 use appgen::schema_spec::*;
 use appgen::sql_gen::*;
 use appgen::view_gen::*;
+use appgen::migration::*;
+use appgen::event_gen::*;
 use clap::{Arg, App};
 
 #[macro_use]
@@ -28,9 +30,10 @@ extern crate serde_derive;
 use rson_rs::de::{from_str, Error};
 
 use std::io::BufWriter;
+use std::process;
 use std::fs::DirBuilder;
 
-fn build_sql(root: &PathBuf, schema: &SchemaSpec) {
+fn build_sql(root: &PathBuf, schema: &SchemaSpec, dialect: Dialect) {
     let mut me = root.clone();
     me.push("sql");
     DirBuilder::new().recursive(true).create(me).unwrap();
@@ -42,13 +45,56 @@ fn build_sql(root: &PathBuf, schema: &SchemaSpec) {
     let mut f = File::create(me).unwrap();
     let mut wr = BufWriter::new(f);
 
-    let _:Vec<()> = schema.tables.iter().map(|v| {
-        write!(wr, "{}\n", String::from(gen_create_sql(v)));
-    }).collect();
+    // sqlite takes every foreign key in with its table
+    if dialect != Dialect::Sqlite {
+        for r in table_order(schema).1 {
+            eprintln!("{}({}) -> {}({}) is part of a cycle, its foreign key is added after the tables",
+                      r.from.table(), r.from.field_list(), r.to.table(), r.to.field_list());
+        }
+    }
 
-    let _:Vec<()> = schema.relationships.iter().map(|v| {
-        write!(wr, "{}\n", gen_create_sql(v));
+    let _:Vec<()> = schema_sql(dialect, schema).iter().map(|v| {
+        write!(wr, "{}\n", v);
     }).collect();
+
+    if schema.seed.len() > 0 {
+        me = root.clone();
+        me.push("sql");
+        me.push("seed.sql");
+
+        let mut wr = BufWriter::new(File::create(me).unwrap());
+        for v in seed_sql(dialect, schema).expect("seed checked by check_schema").iter() {
+            write!(wr, "{}\n", v).unwrap();
+        }
+    }
+}
+
+fn build_migration(root: &PathBuf, old: &SchemaSpec, schema: &SchemaSpec) {
+    let mut me = root.clone();
+    me.push("sql");
+    DirBuilder::new().recursive(true).create(me).unwrap();
+
+    me = root.clone();
+    me.push("sql");
+    me.push("migration.sql");
+
+    let f = File::create(me).unwrap();
+    let mut wr = BufWriter::new(f);
+
+    for m in diff_schema(old, schema).iter() {
+        write!(wr, "{}\n", gen_create_sql(m)).unwrap();
+    }
+}
+
+fn write_dispatcher(root: &PathBuf, schema: &SchemaSpec) {
+    let mut me = root.clone();
+    me.push("events");
+    DirBuilder::new().recursive(true).create(&me).unwrap();
+
+    for (name, contents) in [("dispatcher.js", dispatcher_js(schema)), ("package.json", dispatcher_package())] {
+        let mut f = File::create(me.join(name)).unwrap();
+        f.write_all(contents.as_bytes()).unwrap();
+    }
 }
 
 fn make_admin_tag(rsrc: &str, views: &Vec<(String, String)>) -> JsxNode {
@@ -79,7 +125,7 @@ fn write_jsx(root: &PathBuf, schema: &SchemaSpec, dpdef: (&str, &str)) {
     let global_imports:Vec<Import> = schema.views
         .iter()
         .map(|v| {
-            let (view_name, node, import) = top_level_view_node(&schema.tables, v);
+            let (view_name, node, import) = top_level_view_node(schema, v);
 
             let mut me = root.clone();
             me.push("web");
@@ -130,13 +176,73 @@ fn write_jsx(root: &PathBuf, schema: &SchemaSpec, dpdef: (&str, &str)) {
     }).collect();
      
 
-    let admin_children: Vec<JsxNode> = resource_views.iter()
+    let mut admin_children: Vec<JsxNode> = resource_views.iter()
         .map(|(r, vs)| {
             make_admin_tag(r, vs)
         }).collect();
 
+    // history has no views of its own, the Show views read it from the api
+    admin_children.extend(schema.tables.iter()
+        .filter(|t| t.2.audit && schema.api.contains(&t.0) && resource_views.contains_key(&t.0))
+        .map(|t| make_admin_tag(&t.history_table_name(), &vec!())));
+
+    // the deleted rows are listed in place of the live ones, the api
+    // serves both
+    let soft_deleted = schema.tables.iter()
+        .filter(|t| t.2.soft_delete && schema.api.contains(&t.0) && resource_views.contains_key(&t.0))
+        .collect::<Vec<&Table>>();
+    admin_children.extend(soft_deleted.iter().map(|t| make_admin_tag(&t.deleted_view_name(), &vec!())));
+    if soft_deleted.len() > 0 {
+        let mut me = root.clone();
+        me.push("web");
+        me.push("src");
+        me.push("softDelete.js");
+        let mut f = File::create(me).unwrap();
+        f.write_all(soft_delete_js().as_bytes()).unwrap();
+    }
+
+    // arrays of scalars have widgets of their own
+    if array_tables(schema).iter().any(|t| resource_views.contains_key(&t.0)) {
+        let mut me = root.clone();
+        me.push("web");
+        me.push("src");
+        me.push("arrays.js");
+        let mut f = File::create(me).unwrap();
+        f.write_all(arrays_js().as_bytes()).unwrap();
+    }
+
+    // computed columns are taken out of writes, after any hook has run
+    let data_provider = if computed_tables(schema).len() > 0 {
+        let mut me = root.clone();
+        me.push("web");
+        me.push("src");
+        me.push("computed.js");
+        let mut f = File::create(me).unwrap();
+        f.write_all(computed_js(schema).as_bytes()).unwrap();
+
+        write!(wr, "import {{ withoutComputed }} from './computed';\n");
+        format!("withoutComputed({})", dpdef.0)
+    } else {
+        dpdef.0.to_string()
+    };
+
+    // frontend events run from a wrapper around the data provider
+    let data_provider = if frontend_events(schema).len() > 0 {
+        let mut me = root.clone();
+        me.push("web");
+        me.push("src");
+        me.push("hooks.js");
+        let mut f = File::create(me).unwrap();
+        f.write_all(hooks_js(schema).as_bytes()).unwrap();
+
+        write!(wr, "import {{ withHooks }} from './hooks';\n");
+        format!("withHooks({})", data_provider)
+    } else {
+        data_provider
+    };
+
     let mut hm:HashMap<String, JsxAttr> = HashMap::new();
-    hm.insert("dataProvider".into(), JsxAttr::JsxVariableRef(dpdef.0.into()));
+    hm.insert("dataProvider".into(), JsxAttr::JsxVariableRef(data_provider));
 
     write!(wr, "const App = () => (\n{}\n);\nexport default App;",
         JsxNode::node("Admin", Some(hm), admin_children).to_string(true, "".into()));
@@ -151,7 +257,9 @@ fn read_appspec(filename: &str) -> SchemaSpec {
         .expect("something went wrong reading the file");
 
     let t: Result<SchemaSpec, rson_rs::de::Error> = from_str(&contents);
-    t.unwrap()
+    let mut schema = t.unwrap();
+    schema.add_implied_columns();
+    schema
 }
 
 
@@ -184,6 +292,21 @@ fn main() {
                 .help("Import statement for data provider")
                 .takes_value(true)
                 .required(false))
+        .arg(Arg::with_name("DIALECT")
+                .value_name("DIALECT")
+                .short("d")
+                .long("dialect")
+                .help("Database to generate sql for")
+                .possible_values(&["postgres", "sqlite", "mysql"])
+                .default_value("postgres")
+                .takes_value(true))
+        .arg(Arg::with_name("MIGRATE_FROM")
+                .value_name("OLD_APPSPEC_FILE")
+                .short("m")
+                .long("migrate")
+                .help("Also write sql/migration.sql to upgrade a database built from an older specification")
+                .takes_value(true)
+                .required(false))
         .get_matches();
 
     let dp = (matches.value_of("DATA_PROVIDER_CONSTRUCTOR").unwrap_or("make thing"),
@@ -193,10 +316,39 @@ fn main() {
     let filenames = matches.value_of("INPUT").unwrap();
     let output_path = matches.value_of("OUTPUT").unwrap();
     let schema = read_appspec(filenames);
+    let dialect = Dialect::from_name(matches.value_of("DIALECT").unwrap()).unwrap();
+    if let Err(e) = check_schema(dialect, &schema) {
+        eprintln!("{}: {}", filenames, e);
+        process::exit(1);
+    }
+
+    // everything is checked before anything is written
+    let old = matches.value_of("MIGRATE_FROM").map(|old_filename| {
+        if dialect != Dialect::Postgres {
+            eprintln!("migrations can only be generated for postgres");
+            process::exit(1);
+        }
+        let old = read_appspec(old_filename);
+        if let Err(e) = check_schema(dialect, &old) {
+            eprintln!("{}: {}", old_filename, e);
+            process::exit(1);
+        }
+        if let Err(e) = check_migration(&old, &schema) {
+            eprintln!("{}: {}", filenames, e);
+            process::exit(1);
+        }
+        old
+    });
 
     let target_dir = PathBuf::from(output_path);
 
-    build_sql(&target_dir, &schema);
+    build_sql(&target_dir, &schema, dialect);
+    if let Some(ref old) = old {
+        build_migration(&target_dir, old, &schema);
+    }
+    if endpoint_events(&schema).len() > 0 {
+        write_dispatcher(&target_dir, &schema);
+    }
     write_jsx(&target_dir, &schema, dp);
 }
 
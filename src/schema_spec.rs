@@ -26,7 +26,9 @@ impl From<String> for CreateSql {
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+// Index and Unique take a name, fields sharing a name end up in one
+// multi-column index/constraint. An empty name gets a generated one.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum SqlOption {
     TypeOverride(String),
     Index(String),
@@ -36,30 +38,57 @@ pub enum SqlOption {
 
 
 
-// TODO(matt) - support multivalued primary keys (@nico williams)
-#[derive(Debug, Serialize, Deserialize)]
+// Either "id" or ["order_id", "line_no"] in the schema file
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Columns {
+    Single(String),
+    Composite(Vec<String>)
+}
+
+impl Columns {
+    pub fn names(&self) -> Vec<&String> {
+        match self {
+            Columns::Single(c) => vec!(c),
+            Columns::Composite(cs) => cs.iter().collect()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum Cardinality {
-    One(String, String),
-    Many(String, String)
+    One(String, Columns),
+    Many(String, Columns)
 }
 
 impl Cardinality {
-    pub fn field(&self) -> &String {
+    pub fn fields(&self) -> Vec<&String> {
         match self {
-            Cardinality::One(_, f) => f,
-            Cardinality::Many(_, f) => f,
+            Cardinality::One(_, f) => f.names(),
+            Cardinality::Many(_, f) => f.names(),
         }
     }
 
+    pub fn field_list(&self) -> String {
+        self.fields().iter().map(|f| f.as_str()).collect::<Vec<&str>>().join(", ")
+    }
+
     pub fn table(&self) -> &String {
         match self {
             Cardinality::One(f, _) => f,
             Cardinality::Many(f, _) => f,
         }
     }
+
+    pub fn is_many(&self) -> bool {
+        match self {
+            Cardinality::One(_, _) => false,
+            Cardinality::Many(_, _) => true,
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum Nullable {
     NotNull,
     Null
@@ -71,7 +100,7 @@ impl Default for Nullable {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum Editable {
     WriteOnce,
     ReadOnly,
@@ -89,27 +118,46 @@ impl Default for Editable {
 
 
 
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
 #[serde(default)]
-#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Options {
     pub null: Nullable,
     pub primary_key: bool,
     pub label: String,
-    #[serde(rename = "match")]
+    #[serde(rename = "default")]
     pub default_value: String,
     pub editable: Editable,
-    /* sql: Option<SqlOptions>, */
+    pub sql: Vec<SqlOption>,
+    // left out of the api views, and so out of the ui
+    pub hidden: bool,
+    // sql expression over the row's other columns, the column is stored
+    // as GENERATED ALWAYS AS (...) and can't be written
+    pub computed: String,
     /* uiview: Option<UiViewOptions>, */
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub enum MappedFieldType {
     BigSerialPk,
     Boolean,
     String(usize),
+    Text,
+    Uuid,
+    Json,
+    Jsonb,
+    Bytea,
+    SmallInt,
     Integer,
     Numeric,
-    Timestamp(Option<String>)
+    // precision, scale
+    Decimal(usize, usize),
+    Timestamp(Option<String>),
+    Date,
+    Time,
+    Interval,
+    // (value, label) pairs
+    Enum(Vec<(String, String)>),
+    Array(Box<MappedFieldType>)
 }
 
 impl MappedFieldType {
@@ -117,6 +165,7 @@ impl MappedFieldType {
     // This is the 'heart' of the whole thing
     pub fn view_map_type(&self, v: &ViewKind, o: &Options) -> WidgetDefinition {
         let editting = match (&o.editable, v) {
+            _ if o.computed.len() > 0 => false,
             (Editable::ReadOnly, _) => false,
             (Editable::WriteOnce, ViewKind::Create) => true,
             (Editable::WriteOnce, _) => false,
@@ -138,23 +187,89 @@ impl MappedFieldType {
                 if *sz < 128 { WidgetDefinition::simple("TextInput") } else { WidgetDefinition::simple("LongTextInput") }
             }
             (MappedFieldType::String(sz), false) => WidgetDefinition::simple("TextField"),
+            (MappedFieldType::Text, true) => WidgetDefinition::simple("LongTextInput"),
+            (MappedFieldType::Text, false) => WidgetDefinition::simple("TextField"),
+            (MappedFieldType::Uuid, true) => WidgetDefinition::simple("TextInput"),
+            (MappedFieldType::Uuid, false) => WidgetDefinition::simple("TextField"),
+            // postgrest hands json back parsed, edit it as text
+            (MappedFieldType::Json, true) |
+            (MappedFieldType::Jsonb, true) => {
+                WidgetDefinition::simple("LongTextInput")
+                    .attr("format", "v => (typeof v === 'string' ? v : JSON.stringify(v, null, 2))")
+                    .attr("parse", "v => { try { return JSON.parse(v); } catch (e) { return v; } }")
+            }
+            (MappedFieldType::Json, false) |
+            (MappedFieldType::Jsonb, false) => WidgetDefinition::simple("TextField"),
+            (MappedFieldType::Bytea, true) => WidgetDefinition::simple("TextInput"),
+            (MappedFieldType::Bytea, false) => WidgetDefinition::simple("TextField"),
+            (MappedFieldType::SmallInt, false) => WidgetDefinition::simple("NumberField"),
+            (MappedFieldType::SmallInt, true) => WidgetDefinition::simple("NumberInput"),
+            (MappedFieldType::Decimal(_, scale), false) => {
+                WidgetDefinition::simple("NumberField")
+                    .attr("options", &format!("{{ minimumFractionDigits: {}, maximumFractionDigits: {} }}", scale, scale))
+            }
+            (MappedFieldType::Decimal(_, scale), true) => {
+                WidgetDefinition::simple("NumberInput")
+                    .attr("step", &format!("{}", 1.0 / 10f64.powi(*scale as i32)))
+            }
             (MappedFieldType::Integer, false) => WidgetDefinition::simple("NumberField"),
             (MappedFieldType::Integer, true) => WidgetDefinition::simple("NumberInput"),
             (MappedFieldType::Numeric, false) => WidgetDefinition::simple("NumberField"),
             (MappedFieldType::Numeric, true) => WidgetDefinition::simple("NumberInput"),
-            (MappedFieldType::Timestamp(_), false) => WidgetDefinition::simple("TextField"),
-            (MappedFieldType::Timestamp(_), true) => WidgetDefinition::simple("TextInput"),
+            (MappedFieldType::Timestamp(_), false) => WidgetDefinition::simple("DateField").attr("showTime", "true"),
+            (MappedFieldType::Timestamp(_), true) => WidgetDefinition::simple("DateTimeInput"),
+            (MappedFieldType::Date, false) => WidgetDefinition::simple("DateField"),
+            (MappedFieldType::Date, true) => WidgetDefinition::simple("DateInput"),
+            (MappedFieldType::Time, false) => WidgetDefinition::simple("TextField"),
+            (MappedFieldType::Time, true) => WidgetDefinition::simple("TextInput").attr("type", "\"time\""),
+            (MappedFieldType::Interval, false) => WidgetDefinition::simple("TextField"),
+            (MappedFieldType::Interval, true) => WidgetDefinition::simple("TextInput"),
+            (MappedFieldType::Enum(choices), false) => WidgetDefinition::simple("SelectField").attr("choices", &js_choices(choices)),
+            (MappedFieldType::Enum(choices), true) => WidgetDefinition::simple("SelectInput").attr("choices", &js_choices(choices)),
+            // the items are scalars, react-admin's iterators want records
+            (MappedFieldType::Array(inner), false) => {
+                let w = WidgetDefinition::generated("TextArrayField", "./arrays");
+                match **inner {
+                    MappedFieldType::Enum(ref choices) => w.attr("choices", &js_choices(choices)),
+                    _ => w
+                }
+            }
+            (MappedFieldType::Array(inner), true) => {
+                match **inner {
+                    MappedFieldType::Enum(ref choices) => WidgetDefinition::simple("SelectArrayInput").attr("choices", &js_choices(choices)),
+                    MappedFieldType::SmallInt |
+                    MappedFieldType::Integer |
+                    MappedFieldType::Numeric |
+                    MappedFieldType::Decimal(_, _) => WidgetDefinition::generated("TextArrayInput", "./arrays").attr("type", "\"number\""),
+                    MappedFieldType::Date => WidgetDefinition::generated("TextArrayInput", "./arrays").attr("type", "\"date\""),
+                    MappedFieldType::Time => WidgetDefinition::generated("TextArrayInput", "./arrays").attr("type", "\"time\""),
+                    _ => WidgetDefinition::generated("TextArrayInput", "./arrays")
+                }
+            }
         }
     }
 
 }
 
+fn js_quote(s: &str) -> String {
+    format!("'{}'", s.replace("\\", "\\\\").replace("'", "\\'").replace("\n", "\\n"))
+}
+
+fn js_choices(choices: &Vec<(String, String)>) -> String {
+    let c = choices.iter()
+        .map(|(v, l)| format!("{{ id: {}, name: {} }}", js_quote(v), js_quote(l)))
+        .collect::<Vec<String>>();
+    format!("[{}]", c.join(", "))
+}
+
 // This should be extracted
+// attrs hold javascript expressions, rendered as prop={expr}
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WidgetDefinition {
     name: String,
     pub source: String,
-    attrs: HashMap<String, String>
+    attrs: HashMap<String, String>,
+    children: Vec<WidgetDefinition>
 }
 
 
@@ -165,9 +280,29 @@ impl WidgetDefinition {
         WidgetDefinition {
             name: n.into(),
             source: "react-admin".into(),
-            attrs: HashMap::new()
+            attrs: HashMap::new(),
+            children: vec!()
         }
     }
+
+    // A widget the generator writes out itself, imported from source
+    pub fn generated(n: &str, source: &str) -> WidgetDefinition {
+        WidgetDefinition { source: source.into(), ..WidgetDefinition::simple(n) }
+    }
+
+    pub fn child(mut self, w: WidgetDefinition) -> WidgetDefinition {
+        self.children.push(w);
+        self
+    }
+
+    pub fn children(&self) -> &Vec<WidgetDefinition> { &self.children }
+
+    pub fn attr(mut self, k: &str, v: &str) -> WidgetDefinition {
+        self.attrs.insert(k.into(), v.into());
+        self
+    }
+
+    pub fn attrs(&self) -> &HashMap<String, String> { &self.attrs }
 }
 
 
@@ -175,10 +310,80 @@ impl WidgetDefinition {
 /* pub struct Related {table: String, field: String, cardinality: Cardinality} */
 
 
-#[derive(Debug, Serialize, Deserialize)]
+// What a foreign key does to the rows pointing at a row that is deleted
+// or has its key changed
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum ReferentialAction {
+    NoAction,
+    Restrict,
+    Cascade,
+    SetNull,
+    SetDefault,
+}
+
+impl Default for ReferentialAction {
+    fn default() -> ReferentialAction {
+        ReferentialAction::NoAction
+    }
+}
+
+// When a foreign key is checked, Deferred waits for the commit
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum Deferral {
+    NotDeferrable,
+    Immediate,
+    Deferred,
+}
+
+impl Default for Deferral {
+    fn default() -> Deferral {
+        Deferral::NotDeferrable
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct Relation {
     pub from: Cardinality,
     pub to: Cardinality,
+    #[serde(default)]
+    pub on_delete: ReferentialAction,
+    #[serde(default)]
+    pub on_update: ReferentialAction,
+    #[serde(default)]
+    pub deferrable: Deferral,
+}
+
+impl Relation {
+    // Matches the name postgres picks for an unnamed foreign key
+    pub fn constraint_name(&self) -> String {
+        let cols = self.from.fields().iter().map(|f| f.as_str()).collect::<Vec<&str>>().join("_");
+        format!("{}_{}_fkey", self.from.table(), cols)
+    }
+
+    // Many on both ends can't be a plain foreign key, it goes through a
+    // generated join table instead
+    pub fn is_many_to_many(&self) -> bool {
+        self.from.is_many() && self.to.is_many()
+    }
+
+    // The names postgres picks for the two keys on the join table
+    pub fn join_constraint_names(&self) -> (String, String) {
+        let (from, to) = self.join_columns();
+        let j = self.join_table_name();
+        (format!("{}_{}_fkey", j, from.join("_")), format!("{}_{}_fkey", j, to.join("_")))
+    }
+
+    pub fn join_table_name(&self) -> String {
+        format!("{}_{}", self.from.table(), self.to.table())
+    }
+
+    // Join table columns pointing back at each end, named <table>_<column>
+    pub fn join_columns(&self) -> (Vec<String>, Vec<String>) {
+        let from = self.from.fields().iter().map(|f| format!("{}_{}", self.from.table(), f)).collect();
+        let prefix = if self.from.table() == self.to.table() { "related_" } else { "" };
+        let to = self.to.fields().iter().map(|f| format!("{}{}_{}", prefix, self.to.table(), f)).collect();
+        (from, to)
+    }
 }
 
 
@@ -273,15 +478,15 @@ impl Default for ViewLayout {
     }
 }
 
-#[serde(default)]
 #[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct ActionSpec {
    pub name: String,
    pub params: HashMap<String, String>
 }
 
-#[serde(default)]
 #[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct ViewSpec {
     pub source: UiViewSource,
     pub view: ViewKind,
@@ -295,6 +500,43 @@ pub struct ViewSpec {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Field(pub String, pub MappedFieldType, pub Options);
 
+impl Field {
+    pub fn is_primary_key(&self) -> bool {
+        self.2.primary_key || self.1 == MappedFieldType::BigSerialPk
+    }
+
+    pub fn is_computed(&self) -> bool {
+        self.2.computed.len() > 0
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
+#[serde(default)]
+pub struct TableOptions {
+    // postgres schema the table lives in, empty takes SchemaSpec.namespace
+    pub namespace: String,
+    // every change is copied to a <table>_history table
+    pub audit: bool,
+    // deleting sets deleted_at, the api only shows rows where it is null
+    pub soft_delete: bool,
+}
+
+pub const DELETED_AT: &str = "deleted_at";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Table(pub String, pub Vec<Field>, #[serde(default)] pub TableOptions);
+
+impl Table {
+    pub fn history_table_name(&self) -> String {
+        format!("{}_history", self.0)
+    }
+
+    pub fn deleted_view_name(&self) -> String {
+        format!("{}_deleted", self.0)
+    }
+}
+
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TypeMap ( Vec<TypeMapEntry> );
@@ -325,13 +567,222 @@ pub struct TypeMapEntry {
 }
 
 
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum Permission {
+    Select,
+    Insert,
+    Update,
+    Delete
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
+#[serde(default)]
+pub struct AclSpec {
+    pub role: String,
+    // table the grant is on
+    pub resource: String,
+    pub permissions: Vec<Permission>,
+    // empty grants the whole table
+    pub columns: Vec<String>,
+    // row level security predicate, empty lets the role at every row
+    pub policy: String,
+}
+
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum EventKind {
+    Insert,
+    Update,
+    Delete
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum EventTiming {
+    Before,
+    After
+}
+
+impl Default for EventTiming {
+    fn default() -> EventTiming {
+        EventTiming::After
+    }
+}
+
+// Where the handler of an event runs
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub enum LocationDef {
+    // plpgsql in a trigger on the table
+    Backend,
+    // POSTed to a url by the generated dispatcher, once the change commits
+    Endpoint { url: String },
+    // js in the generated app, run by its data provider around each write
+    Frontend,
+}
+
+impl Default for LocationDef {
+    fn default() -> LocationDef {
+        LocationDef::Backend
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
+#[serde(default)]
+pub struct EventSpec {
+    pub event_name: String,
+    pub table: String,
+    pub on: Vec<EventKind>,
+    pub event_location: LocationDef,
+    pub timing: EventTiming,
+    // sql condition on OLD/NEW, the handler only runs when it holds
+    pub when: String,
+    pub source: String,
+}
+
+
+// Rows created along with the schema. Each row maps column to value, in
+// the same form as a column default, a column left out takes its default
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
+#[serde(default)]
+pub struct SeedSpec {
+    pub table: String,
+    pub rows: Vec<HashMap<String, SeedValue>>,
+}
+
+// None in the schema file puts a null in a nullable column
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum SeedValue {
+    Value(String),
+    Null(Option<()>),
+}
+
+
+// An <other table>_ids array on an api view, read from and written to
+// the join table of a many-to-many relation. key is the column of this
+// table the join table points at, this and other its columns on each end
+#[derive(Debug, PartialEq)]
+pub struct IdArray<'a> {
+    pub column: String,
+    pub table: String,
+    pub relation: &'a Relation,
+    pub key: String,
+    pub this: String,
+    pub other: String,
+}
+
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SchemaSpec {
-    pub tables: Vec<(String, Vec<Field>)>,
+    pub tables: Vec<Table>,
     pub relationships: Vec<Relation>,
     pub views: Vec<ViewSpec>,
     pub api: Vec<String>,
-    pub acl: Vec<String>,
+    pub acl: Vec<AclSpec>,
+    #[serde(default)]
+    pub namespace: String,
+    #[serde(default)]
+    pub events: Vec<EventSpec>,
+    #[serde(default)]
+    pub seed: Vec<SeedSpec>,
+}
+
+impl SchemaSpec {
+    // A join table goes with the table on the from side of its relation,
+    // a history table with the table it records
+    pub fn namespace_of(&self, table: &str) -> &str {
+        let owner = self.relationships.iter()
+            .find(|r| r.is_many_to_many() && r.join_table_name() == table)
+            .map(|r| r.from.table().as_str())
+            .or_else(|| self.tables.iter().find(|t| t.2.audit && t.history_table_name() == table).map(|t| t.0.as_str()))
+            .unwrap_or(table);
+
+        self.tables.iter()
+            .find(|t| t.0 == owner)
+            .map(|t| t.2.namespace.as_str())
+            .filter(|n| n.len() > 0)
+            .unwrap_or(&self.namespace)
+    }
+
+    pub fn acl_for(&self, table: &str) -> Vec<&AclSpec> {
+        self.acl.iter().filter(|a| a.resource == table).collect()
+    }
+
+    // Every role the acl mentions, in the order they first appear
+    pub fn roles(&self) -> Vec<&str> {
+        let mut out = vec!();
+        for a in self.acl.iter() {
+            if !out.contains(&a.role.as_str()) {
+                out.push(a.role.as_str());
+            }
+        }
+        out
+    }
+
+    // Every namespace in use, in the order they first appear
+    pub fn namespaces(&self) -> Vec<&str> {
+        let mut out = vec!();
+        for t in self.tables.iter() {
+            let n = self.namespace_of(&t.0);
+            if n.len() > 0 && !out.contains(&n) {
+                out.push(n);
+            }
+        }
+        out
+    }
+
+    // Tables whose rows go when a row of table is deleted, following
+    // cascades through the tables they reach. Join tables are left out,
+    // losing a row there only unlinks
+    pub fn cascaded_tables(&self, table: &str) -> Vec<&str> {
+        let mut out: Vec<&str> = vec!();
+        let mut next = vec!(table);
+        while let Some(t) = next.pop() {
+            for r in self.relationships.iter() {
+                let from = r.from.table().as_str();
+                if !r.is_many_to_many() && r.on_delete == ReferentialAction::Cascade && r.to.table() == t
+                    && from != table && !out.contains(&from) {
+                    out.push(from);
+                    next.push(from);
+                }
+            }
+        }
+        out
+    }
+
+    // The id arrays a table's api view carries, one per many-to-many
+    // relation touching it whose ends are a single column
+    pub fn id_arrays(&self, table: &str) -> Vec<IdArray<'_>> {
+        if !self.api.iter().any(|a| a == table) {
+            return vec!();
+        }
+        self.relationships.iter()
+            .filter(|r| r.is_many_to_many() && r.from.fields().len() == 1 && r.to.fields().len() == 1)
+            .filter_map(|r| {
+                let (from, to) = r.join_columns();
+                if r.from.table() == table {
+                    let prefix = if r.to.table() == table { "related_" } else { "" };
+                    Some(IdArray { column: format!("{}{}_ids", prefix, r.to.table()), table: r.to.table().clone(), relation: r,
+                                   key: r.from.fields()[0].clone(), this: from[0].clone(), other: to[0].clone() })
+                } else if r.to.table() == table {
+                    Some(IdArray { column: format!("{}_ids", r.from.table()), table: r.from.table().clone(), relation: r,
+                                   key: r.to.fields()[0].clone(), this: to[0].clone(), other: from[0].clone() })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    // Adds the columns table options bring with them, unless the schema
+    // already declares them. Run once, straight after loading
+    pub fn add_implied_columns(&mut self) {
+        for t in self.tables.iter_mut().filter(|t| t.2.soft_delete) {
+            if !t.1.iter().any(|f| f.0 == DELETED_AT) {
+                let o = Options { null: Nullable::Null, hidden: true, editable: Editable::ReadOnly, ..Default::default() };
+                t.1.push(Field(DELETED_AT.into(), MappedFieldType::Timestamp(Some("UTC".into())), o));
+            }
+        }
+    }
 }
 
 
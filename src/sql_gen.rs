@@ -6,82 +6,2106 @@ use schema_spec::*;
 use std::convert::From;
 
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+    Postgres,
+    Sqlite,
+    MySql,
+}
+
+impl Dialect {
+    pub fn from_name(n: &str) -> Option<Dialect> {
+        match n.to_lowercase().as_str() {
+            "postgres" | "postgresql" => Some(Dialect::Postgres),
+            "sqlite" => Some(Dialect::Sqlite),
+            "mysql" | "mariadb" => Some(Dialect::MySql),
+            _ => None
+        }
+    }
+}
+
+// A schema item rendered for a particular database, the plain
+// From<&A> conversions target postgres
+pub struct ForDialect<'a, A: 'a>(pub Dialect, pub &'a A);
+
+// Keywords postgres or sqlite won't take as a bare table or column name
+const RESERVED: &[&str] = &[
+    "add", "all", "alter", "analyse", "analyze", "and", "any", "array", "as", "asc",
+    "asymmetric", "authorization", "autoincrement", "between", "binary", "both", "case",
+    "cast", "check", "collate", "collation", "column", "commit", "concurrently",
+    "constraint", "create", "cross", "current_catalog", "current_date", "current_role",
+    "current_schema", "current_time", "current_timestamp", "current_user", "default",
+    "deferrable", "delete", "desc", "distinct", "do", "drop", "else", "end", "escape",
+    "except", "exists", "false", "fetch", "for", "foreign", "freeze", "from", "full",
+    "grant", "group", "having", "if", "ilike", "in", "index", "initially", "inner",
+    "insert", "intersect", "into", "is", "isnull", "join", "lateral", "leading", "left",
+    "like", "limit", "localtime", "localtimestamp", "natural", "not", "nothing", "notnull",
+    "null", "offset", "on", "only", "or", "order", "outer", "overlaps", "placing",
+    "primary", "references", "returning", "right", "select", "session_user", "set",
+    "similar", "some", "symmetric", "table", "tablesample", "then", "to", "trailing",
+    "transaction", "true", "union", "unique", "update", "user", "using", "values",
+    "variadic", "verbose", "when", "where", "window", "with",
+];
+
+fn is_bare_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let first = chars.next().map(|c| c == '_' || c.is_ascii_lowercase()).unwrap_or(false);
+    first && chars.all(|c| c == '_' || c.is_ascii_lowercase() || c.is_ascii_digit())
+        && !RESERVED.contains(&name)
+}
+
+// Postgres folds unquoted names to lower case, so anything else is quoted.
+// Quoting everything on mysql is the norm there
+pub fn quote(d: Dialect, name: &str) -> String {
+    match d {
+        Dialect::MySql => format!("`{}`", name.replace("`", "``")),
+        _ if is_bare_identifier(name) => name.to_string(),
+        _ => format!("\"{}\"", name.replace("\"", "\"\""))
+    }
+}
+
+// The longest name each database keeps, postgres silently truncates
+fn max_identifier_len(d: Dialect) -> Option<usize> {
+    match d {
+        Dialect::Postgres => Some(63),
+        Dialect::MySql => Some(64),
+        Dialect::Sqlite => None
+    }
+}
+
+fn check_identifier(d: Dialect, kind: &str, name: &str) -> Result<(), String> {
+    if name.len() == 0 {
+        return Err(format!("empty {} name", kind));
+    }
+    if name.chars().any(|c| c.is_control()) {
+        return Err(format!("{} name {:?} has control characters", kind, name));
+    }
+    // react-admin reads a dotted source as a path into the record
+    if name.contains('.') {
+        return Err(format!("{} name {:?} can not contain '.'", kind, name));
+    }
+    match max_identifier_len(d) {
+        Some(max) if name.len() > max => {
+            Err(format!("{} name {:?} is longer than {} bytes", kind, name, max))
+        }
+        _ => Ok(())
+    }
+}
+
+// Tables also name the generated components and their files
+fn check_table_name(d: Dialect, name: &str) -> Result<(), String> {
+    check_identifier(d, "table", name)?;
+    let mut chars = name.chars();
+    let first = chars.next().map(|c| c == '_' || c.is_ascii_alphabetic()).unwrap_or(false);
+    if first && chars.all(|c| c == '_' || c.is_ascii_alphanumeric()) {
+        Ok(())
+    } else {
+        Err(format!("table name {:?} must be letters, digits and '_', not starting with a digit", name))
+    }
+}
+
+// Tables outside a namespace are left to the search_path
+pub fn qualify(d: Dialect, namespace: &str, name: &str) -> String {
+    if namespace.len() > 0 {
+        format!("{}.{}", quote(d, namespace), quote(d, name))
+    } else {
+        quote(d, name)
+    }
+}
+
+pub fn table_ref(d: Dialect, s: &SchemaSpec, table: &str) -> String {
+    qualify(d, s.namespace_of(table), table)
+}
+
+fn quote_list<S: AsRef<str>>(d: Dialect, names: &[S]) -> String {
+    names.iter().map(|n| quote(d, n.as_ref())).collect::<Vec<String>>().join(", ")
+}
+
+// mysql reads backslash escapes inside string literals
+fn string_literal(d: Dialect, v: &str) -> String {
+    match d {
+        Dialect::MySql => format!("'{}'", v.replace("\\", "\\\\").replace("'", "''")),
+        _ => format!("'{}'", v.replace("'", "''"))
+    }
+}
+
+
 pub fn gen_create_sql<'a, A>(a: &'a A) -> String
   where CreateSql: From<&'a A>
 {
   String::from(CreateSql::from(a))
 }
 
-impl<'a> From<&'a Field> for CreateSql {
+// A TypeOverride wins over the mapped type
+pub fn column_type(d: Dialect, f: &Field) -> String {
+    f.2.sql.iter()
+        .filter_map(|o| match o {
+            SqlOption::TypeOverride(t) => Some(t.clone()),
+            _ => None
+        })
+        .next_back()
+        .unwrap_or_else(|| gen_create_sql(&ForDialect(d, &f.1)))
+}
+
+impl<'a, 'b> From<&'b ForDialect<'a, Field>> for CreateSql {
+
+    fn from(fd: &ForDialect<Field>) -> CreateSql {
+        let ForDialect(d, f) = *fd;
+        let mut sql = format!("{} {}", quote(d, &f.0), column_type(d, f));
+
+        // mysql wants the expression ahead of NOT NULL
+        if f.is_computed() {
+            sql += &format!(" GENERATED ALWAYS AS ({}) STORED", f.2.computed.trim());
+        }
+
+        // serial columns already carry their own primary key, the rest
+        // are declared once for the table
+        if f.1 != MappedFieldType::BigSerialPk && f.2.null == Nullable::NotNull {
+            sql += " NOT NULL";
+        }
+
+        if f.2.default_value.len() > 0 {
+            // check_schema has already rejected bad literals
+            if let Ok(lit) = sql_literal(d, &f.1, &f.2.default_value) {
+                match d {
+                    Dialect::MySql => sql += &format!(" DEFAULT {}", mysql_default(&f.1, lit)),
+                    _ => sql += &format!(" DEFAULT {}", lit)
+                }
+            }
+        }
+        sql.into()
+    }
+}
 
+impl<'a> From<&'a Field> for CreateSql {
     fn from(f: &Field) -> CreateSql {
-        format!("{} {}", f.0, gen_create_sql(&f.1)).into()
+        CreateSql::from(&ForDialect(Dialect::Postgres, f))
+    }
+}
+
+// mysql only takes constants and CURRENT_TIMESTAMP as plain defaults,
+// anything else, or any default on a blob/text column, is an expression
+fn mysql_default(t: &MappedFieldType, lit: String) -> String {
+    match t {
+        MappedFieldType::Text |
+        MappedFieldType::Json |
+        MappedFieldType::Jsonb |
+        MappedFieldType::Bytea |
+        MappedFieldType::Array(_) => format!("({})", lit),
+        MappedFieldType::Date |
+        MappedFieldType::Time if !lit.starts_with('\'') => format!("({})", lit),
+        _ => lit
     }
 }
 
 
+fn is_decimal(v: &str) -> bool {
+    let digits = v.trim_start_matches('-');
+    let mut parts = digits.splitn(2, '.');
+    let whole = parts.next().unwrap_or("");
+    let frac = parts.next().unwrap_or("");
+    whole.len() + frac.len() > 0 && whole.chars().chain(frac.chars()).all(|c| c.is_ascii_digit())
+}
+
+fn is_digits(v: &str) -> bool {
+    v.len() > 0 && v.chars().all(|c| c.is_ascii_digit())
+}
+
+fn in_range(digits: &str, min: u32, max: u32) -> bool {
+    is_digits(digits) && digits.parse::<u32>().map(|n| n >= min && n <= max).unwrap_or(false)
+}
+
+// YYYY-MM-DD, then optionally the time after a T or a space and an offset
+// from UTC
+fn is_timestamp(v: &str) -> bool {
+    if !v.is_ascii() || v.len() < 10 || !is_date(&v[..10]) {
+        return false;
+    }
+    let rest = &v[10..];
+    if rest.len() == 0 {
+        return true;
+    }
+    if !rest.starts_with('T') && !rest.starts_with(' ') {
+        return false;
+    }
+    let rest = &rest[1..];
+    let (time, offset) = rest.split_at(rest.find(['Z', '+', '-']).unwrap_or(rest.len()));
+    is_time(time) && (offset.len() == 0 || is_offset(offset))
+}
+
+// Z, or +HH, +HHMM or +HH:MM either side of UTC
+fn is_offset(v: &str) -> bool {
+    if v == "Z" {
+        return true;
+    }
+    if !v.starts_with('+') && !v.starts_with('-') {
+        return false;
+    }
+    let (hours, minutes) = match v.len() {
+        3 => (&v[1..3], "00"),
+        5 => (&v[1..3], &v[3..5]),
+        6 if &v[3..4] == ":" => (&v[1..3], &v[4..6]),
+        _ => return false
+    };
+    hours.len() == 2 && in_range(hours, 0, 23) && minutes.len() == 2 && in_range(minutes, 0, 59)
+}
+
+fn is_uuid(v: &str) -> bool {
+    let groups = v.split('-').map(|g| g.len()).collect::<Vec<usize>>();
+    groups == vec!(8, 4, 4, 4, 12) && v.chars().all(|c| c == '-' || c.is_ascii_hexdigit())
+}
+
+fn is_date(v: &str) -> bool {
+    let b = v.as_bytes();
+    b.len() == 10 && b.iter().enumerate().all(|(i, c)| {
+        if i == 4 || i == 7 { *c == b'-' } else { c.is_ascii_digit() }
+    }) && in_range(&v[5..7], 1, 12) && in_range(&v[8..10], 1, 31)
+}
+
+// HH:MM or HH:MM:SS, the seconds can take a fraction
+fn is_time(v: &str) -> bool {
+    let (clock, fraction) = match v.find('.') {
+        Some(i) => (&v[..i], Some(&v[i + 1..])),
+        None => (v, None)
+    };
+    let parts = clock.split(':').collect::<Vec<&str>>();
+    (parts.len() == 2 || parts.len() == 3) &&
+        parts.iter().zip(&[23, 59, 59]).all(|(p, max)| p.len() == 2 && in_range(p, 0, *max)) &&
+        fraction.map(|f| parts.len() == 3 && is_digits(f)).unwrap_or(true)
+}
+
+fn is_number_type(t: &MappedFieldType) -> bool {
+    match t {
+        MappedFieldType::SmallInt |
+        MappedFieldType::Integer |
+        MappedFieldType::Numeric |
+        MappedFieldType::Decimal(_, _) |
+        MappedFieldType::Boolean => true,
+        _ => false
+    }
+}
+
+// Checks a literal from the schema file against the column type and
+// renders it as sql
+pub fn sql_literal(d: Dialect, t: &MappedFieldType, v: &str) -> Result<String, String> {
+    match t {
+        MappedFieldType::BigSerialPk => {
+            Err("serial columns take their value from a sequence".into())
+        }
+        MappedFieldType::Boolean => {
+            match (v.to_lowercase().as_str(), d) {
+                ("true", Dialect::Sqlite) => Ok("1".into()),
+                ("false", Dialect::Sqlite) => Ok("0".into()),
+                ("true", _) => Ok("TRUE".into()),
+                ("false", _) => Ok("FALSE".into()),
+                _ => Err(format!("'{}' is not a boolean", v))
+            }
+        }
+        MappedFieldType::String(max_length) => {
+            if v.chars().count() > *max_length {
+                Err(format!("'{}' is longer than {} characters", v, max_length))
+            } else {
+                Ok(string_literal(d, v))
+            }
+        }
+        MappedFieldType::Text => {
+            Ok(string_literal(d, v))
+        }
+        MappedFieldType::Uuid => {
+            match v {
+                "gen_random_uuid()" if d == Dialect::Postgres => Ok(v.into()),
+                _ if is_uuid(v) => Ok(format!("'{}'", v)),
+                _ => Err(format!("'{}' is not a uuid", v))
+            }
+        }
+        MappedFieldType::Json |
+        MappedFieldType::Jsonb => {
+            serde_json::from_str::<serde_json::Value>(v)
+                .map(|_| string_literal(d, v))
+                .map_err(|e| format!("'{}' is not json, {}", v, e))
+        }
+        MappedFieldType::Bytea => {
+            let hex = v.trim_start_matches("\\x");
+            if v.starts_with("\\x") && hex.len() % 2 == 0 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                match d {
+                    Dialect::Postgres => Ok(format!("'{}'", v)),
+                    Dialect::Sqlite |
+                    Dialect::MySql => Ok(format!("X'{}'", hex))
+                }
+            } else {
+                Err(format!("'{}' is not a \\x hex string", v))
+            }
+        }
+        MappedFieldType::SmallInt => {
+            v.parse::<i16>()
+                .map(|i| i.to_string())
+                .map_err(|_| format!("'{}' is not a small integer", v))
+        }
+        MappedFieldType::Integer => {
+            v.parse::<i64>()
+                .map(|i| i.to_string())
+                .map_err(|_| format!("'{}' is not an integer", v))
+        }
+        MappedFieldType::Numeric => {
+            if is_decimal(v) { Ok(v.into()) } else { Err(format!("'{}' is not a number", v)) }
+        }
+        MappedFieldType::Decimal(precision, scale) => {
+            let digits = v.trim_start_matches('-');
+            let mut parts = digits.splitn(2, '.');
+            let whole = parts.next().unwrap_or("").trim_start_matches('0').len();
+            let frac = parts.next().unwrap_or("").len();
+            // check_type turns these down, this can be reached before it
+            let places = precision.checked_sub(*scale)
+                .ok_or_else(|| format!("Decimal({}, {}) has a scale above its precision", precision, scale))?;
+            if !is_decimal(v) {
+                Err(format!("'{}' is not a number", v))
+            } else if frac > *scale || whole > places {
+                Err(format!("'{}' does not fit Decimal({}, {})", v, precision, scale))
+            } else {
+                Ok(v.into())
+            }
+        }
+        MappedFieldType::Timestamp(_) => {
+            match v.to_lowercase().as_str() {
+                "now" | "now()" | "current_timestamp" => Ok("CURRENT_TIMESTAMP".into()),
+                _ if is_timestamp(v) => Ok(string_literal(d, v)),
+                _ => Err(format!("'{}' is not a timestamp", v))
+            }
+        }
+        MappedFieldType::Date => {
+            match v.to_lowercase().as_str() {
+                "today" | "current_date" => Ok("CURRENT_DATE".into()),
+                _ if is_date(v) => Ok(format!("'{}'", v)),
+                _ => Err(format!("'{}' is not a date", v))
+            }
+        }
+        MappedFieldType::Time => {
+            match v.to_lowercase().as_str() {
+                "now" | "current_time" if d == Dialect::Postgres => Ok("LOCALTIME".into()),
+                "now" | "current_time" => Ok("CURRENT_TIME".into()),
+                _ if is_time(v) => Ok(format!("'{}'", v)),
+                _ => Err(format!("'{}' is not a time of day", v))
+            }
+        }
+        // postgres accepts too many interval spellings to check them here
+        MappedFieldType::Interval => {
+            Ok(string_literal(d, v))
+        }
+        MappedFieldType::Enum(choices) => {
+            if choices.iter().any(|c| c.0 == v) {
+                Ok(string_literal(d, v))
+            } else {
+                Err(format!("'{}' is not one of the choices", v))
+            }
+        }
+        // postgres array syntax, {a,b,c}
+        MappedFieldType::Array(inner) => {
+            if !v.starts_with('{') || !v.ends_with('}') {
+                return Err(format!("'{}' is not an array, expected {{a,b,...}}", v));
+            }
+            let body = v[1..v.len() - 1].trim();
+            let raw = body.split(',')
+                .map(|i| i.trim().trim_matches('"'))
+                .filter(|i| i.len() > 0)
+                .collect::<Vec<&str>>();
+            let items = raw.iter()
+                .map(|i| sql_literal(d, inner, i))
+                .collect::<Result<Vec<String>, String>>()?;
+
+            match d {
+                Dialect::Postgres if items.len() == 0 => Ok("'{}'".into()),
+                Dialect::Postgres => Ok(format!("ARRAY[{}]::{}", items.join(", "), gen_create_sql(&ForDialect(d, t)))),
+                // no arrays, they are stored as json text
+                Dialect::Sqlite |
+                Dialect::MySql => {
+                    let json = raw.iter()
+                        .zip(items.iter())
+                        .map(|(r, i)| if is_number_type(inner) { i.clone() } else { serde_json::Value::from(*r).to_string() })
+                        .collect::<Vec<String>>();
+                    Ok(string_literal(d, &format!("[{}]", json.join(","))))
+                }
+            }
+        }
+    }
+}
+
+
+fn check_columns(s: &SchemaSpec, c: &Cardinality) -> Result<(), String> {
+    let t = s.tables.iter().find(|t| t.0 == *c.table())
+        .ok_or_else(|| format!("relationship to unknown table {}", c.table()))?;
+
+    for name in c.fields() {
+        if !t.1.iter().any(|f| f.0 == *name) {
+            return Err(format!("relationship to unknown column {}.{}", t.0, name));
+        }
+    }
+    Ok(())
+}
+
+
+// Setting the referencing columns has to leave them something they can
+// hold, a join table's columns are its key so they can only cascade
+fn check_actions(d: Dialect, s: &SchemaSpec, r: &Relation) -> Result<(), String> {
+    if d == Dialect::MySql && r.deferrable != Deferral::NotDeferrable {
+        return Err("mysql checks foreign keys straight away, they can't be deferrable".into());
+    }
+    let t = s.tables.iter().find(|t| t.0 == *r.from.table());
+    let computed = t.map(|t| t.1.iter().any(|f| f.is_computed() && r.from.fields().contains(&&f.0))).unwrap_or(false);
+    for a in [r.on_delete, r.on_update] {
+        if computed && a != ReferentialAction::NoAction && a != ReferentialAction::Restrict {
+            return Err(format!("{} would write to a computed column", referential_action(a)));
+        }
+        if d == Dialect::MySql && a == ReferentialAction::SetDefault {
+            return Err("mysql does not support SET DEFAULT".into());
+        }
+        if a != ReferentialAction::SetNull && a != ReferentialAction::SetDefault {
+            continue;
+        }
+        if r.is_many_to_many() {
+            return Err(format!("{} on a many-to-many, the join table's columns are its key", referential_action(a)));
+        }
+        let t = s.tables.iter().find(|t| t.0 == *r.from.table()).unwrap();
+        for c in r.from.fields() {
+            let f = t.1.iter().find(|f| f.0 == *c).unwrap();
+            let nullable = f.2.null == Nullable::Null && !f.is_primary_key();
+            if !nullable && (a == ReferentialAction::SetNull || f.2.default_value.len() == 0) {
+                return Err(format!("{} on {}.{}, which can't be null", referential_action(a), t.0, c));
+            }
+        }
+    }
+    Ok(())
+}
+
 
+fn key_type(s: &SchemaSpec, table: &str, column: &str) -> MappedFieldType {
+    let t = s.tables.iter()
+        .find(|t| t.0 == table)
+        .and_then(|t| t.1.iter().find(|f| f.0 == column).map(|f| f.1.clone()));
+
+    match t {
+        Some(MappedFieldType::BigSerialPk) | None => MappedFieldType::Integer,
+        Some(t) => t
+    }
+}
+
+// The generated table behind a many-to-many relation, keyed on both ends
+pub fn join_table(s: &SchemaSpec, r: &Relation) -> Option<Table> {
+    if !r.is_many_to_many() {
+        return None;
+    }
+
+    let (from_cols, to_cols) = r.join_columns();
+    let mut fields = vec!();
+    for (end, cols) in [(&r.from, from_cols), (&r.to, to_cols)] {
+        for (c, referenced) in cols.into_iter().zip(end.fields()) {
+            let o = Options { primary_key: true, ..Default::default() };
+            fields.push(Field(c, key_type(s, end.table(), referenced), o));
+        }
+    }
+    let o = TableOptions { namespace: s.namespace_of(r.from.table()).to_string(), ..Default::default() };
+    Some(Table(r.join_table_name(), fields, o))
+}
+
+pub fn join_tables(s: &SchemaSpec) -> Vec<Table> {
+    s.relationships.iter().filter_map(|r| join_table(s, r)).collect()
+}
+
+// The table an audited table's changes are copied to, the rows go in as
+// jsonb so it does not have to follow the table's columns around
+pub fn history_table(s: &SchemaSpec, t: &Table) -> Option<Table> {
+    if !t.2.audit {
+        return None;
+    }
+
+    let key = t.1.iter().find(|f| f.is_primary_key()).map(|f| f.0.as_str()).unwrap_or("id");
+    let column = |name: &str, ty: MappedFieldType, o: Options| Field(name.into(), ty, o);
+    let fields = vec!(
+        column("id", MappedFieldType::BigSerialPk, Default::default()),
+        column("record_id", key_type(s, &t.0, key), Options { sql: vec!(SqlOption::Index("".into())), ..Default::default() }),
+        column("operation", MappedFieldType::String(6), Default::default()),
+        column("changed_at", MappedFieldType::Timestamp(Some("UTC".into())), Options { default_value: "now".into(), ..Default::default() }),
+        column("changed_by", MappedFieldType::Text, Default::default()),
+        column("old_row", MappedFieldType::Jsonb, Options { null: Nullable::Null, ..Default::default() }),
+        column("new_row", MappedFieldType::Jsonb, Options { null: Nullable::Null, ..Default::default() }));
+    let o = TableOptions { namespace: s.namespace_of(&t.0).to_string(), ..Default::default() };
+    Some(Table(t.history_table_name(), fields, o))
+}
+
+// (create, drop) for the history of an audited table. The trigger function
+// runs as its owner so writers need no grant on the history and can't
+// write to it themselves, role still holds whoever the caller set. A
+// history outlives its table and the audit option, so drop leaves it be
+pub fn audit_sql(s: &SchemaSpec, t: &Table) -> Option<(String, String)> {
+    let d = Dialect::Postgres;
+    let h = history_table(s, t)?;
+    // the function's search_path leaves out the default namespace
+    let namespace = if s.namespace_of(&t.0).len() > 0 { s.namespace_of(&t.0) } else { "public" };
+    let history = qualify(d, namespace, &h.0);
+    let table = table_ref(d, s, &t.0);
+    let function = qualify(d, s.namespace_of(&t.0), &format!("{}_audit", t.0));
+    let key = quote(d, t.1.iter().find(|f| f.is_primary_key()).map(|f| f.0.as_str()).unwrap_or("id"));
+
+    let create = format!("{}\n\
+                          CREATE FUNCTION {}() RETURNS trigger\n\
+                          LANGUAGE plpgsql SECURITY DEFINER SET search_path = pg_catalog, pg_temp AS $body$\n\
+                          BEGIN\n  \
+                          INSERT INTO {} (record_id, operation, changed_by, old_row, new_row)\n  \
+                          VALUES (CASE WHEN TG_OP = 'DELETE' THEN OLD.{} ELSE NEW.{} END, TG_OP,\n          \
+                          coalesce(nullif(current_setting('role'), 'none'), session_user), to_jsonb(OLD), to_jsonb(NEW));\n  \
+                          RETURN NULL;\n\
+                          END;\n\
+                          $body$;\n\
+                          CREATE TRIGGER audit AFTER INSERT OR UPDATE OR DELETE ON {}\n  \
+                          FOR EACH ROW\n  \
+                          EXECUTE FUNCTION {}();",
+                         create_table(d, s.namespace_of(&t.0), &h, vec!())
+                             .replacen("CREATE TABLE", "CREATE TABLE IF NOT EXISTS", 1)
+                             .replace("CREATE INDEX", "CREATE INDEX IF NOT EXISTS"),
+                         function, history, key, key, table, function);
+    let drop = format!("DROP TRIGGER IF EXISTS audit ON {};\nDROP FUNCTION IF EXISTS {}();", table, function);
+    Some((create, drop))
+}
+
+
+pub fn check_type(t: &MappedFieldType) -> Result<(), String> {
+    match t {
+        MappedFieldType::Decimal(precision, scale) => {
+            if *precision == 0 || scale > precision {
+                return Err(format!("Decimal({}, {}) needs 0 < precision and scale <= precision", precision, scale));
+            }
+        }
+        MappedFieldType::Enum(choices) => {
+            if choices.len() == 0 {
+                return Err("an enum needs at least one choice".into());
+            }
+            if choices.iter().enumerate().any(|(i, c)| choices[..i].iter().any(|p| p.0 == c.0)) {
+                return Err("enum choices must be unique".into());
+            }
+        }
+        MappedFieldType::Array(inner) => {
+            match **inner {
+                MappedFieldType::BigSerialPk |
+                MappedFieldType::Array(_) => {
+                    return Err(format!("can not make an array of {:?}", inner));
+                }
+                _ => check_type(inner)?
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+
+pub fn check_schema(d: Dialect, s: &SchemaSpec) -> Result<(), String> {
+    for n in s.namespaces() {
+        if d != Dialect::Postgres {
+            return Err(format!("namespace {}: namespaces are only supported on postgres", n));
+        }
+        check_identifier(d, "namespace", n)?;
+    }
+
+    for t in s.tables.iter() {
+        check_table_name(d, &t.0)?;
+        let pk = t.1.iter().filter(|f| f.is_primary_key()).collect::<Vec<&Field>>();
+        if pk.len() > 1 && pk.iter().any(|f| f.1 == MappedFieldType::BigSerialPk) {
+            return Err(format!("{}: a serial primary key can not be part of a composite key", t.0));
+        }
+
+        for f in t.1.iter() {
+            check_identifier(d, "column", &f.0).map_err(|e| format!("{}: {}", t.0, e))?;
+
+            if f.is_primary_key() && f.2.null == Nullable::Null {
+                return Err(format!("{}.{}: a primary key can not be null", t.0, f.0));
+            }
+
+            check_type(&f.1).map_err(|e| format!("{}.{}: {}", t.0, f.0, e))?;
+
+            if f.2.default_value.len() > 0 {
+                sql_literal(d, &f.1, &f.2.default_value)
+                    .map_err(|e| format!("{}.{}: bad default, {}", t.0, f.0, e))?;
+            }
+
+            if f.is_computed() {
+                if f.is_primary_key() {
+                    return Err(format!("{}.{}: a computed column can not be part of the primary key", t.0, f.0));
+                }
+                if f.2.default_value.len() > 0 {
+                    return Err(format!("{}.{}: a computed column can not have a default", t.0, f.0));
+                }
+                if f.2.computed.trim().len() == 0 {
+                    return Err(format!("{}.{}: empty computed expression", t.0, f.0));
+                }
+            }
+
+            for o in f.2.sql.iter() {
+                match o {
+                    SqlOption::TypeOverride(v) |
+                    SqlOption::Constraint(v) if v.trim().len() == 0 => {
+                        return Err(format!("{}.{}: empty {:?}", t.0, f.0, o));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    check_audit(d, s)?;
+    check_soft_delete(s)?;
+    check_api(d, s)?;
+    check_acl(d, s)?;
+    check_events(d, s)?;
+    check_seed(d, s)?;
+
+    for r in s.relationships.iter() {
+        check_columns(s, &r.from)?;
+        check_columns(s, &r.to)?;
+        check_actions(d, s, r).map_err(|e| format!("relationship {}({}) -> {}({}): {}",
+                                                    r.from.table(), r.from.field_list(), r.to.table(), r.to.field_list(), e))?;
+        if r.is_many_to_many() {
+            if s.tables.iter().any(|t| t.0 == r.join_table_name()) {
+                return Err(format!("join table {} clashes with a declared table", r.join_table_name()));
+            }
+            check_table_name(d, &r.join_table_name())?;
+            let (from_cols, to_cols) = r.join_columns();
+            for c in from_cols.iter().chain(to_cols.iter()) {
+                check_identifier(d, "column", c).map_err(|e| format!("{}: {}", r.join_table_name(), e))?;
+            }
+        } else if r.from.fields().len() != r.to.fields().len() {
+            return Err(format!("relationship {}({}) -> {}({}) has mismatched columns",
+                               r.from.table(), r.from.field_list(), r.to.table(), r.to.field_list()));
+        }
+    }
+    Ok(())
+}
+
+
+
+impl<'a, 'b> From<&'b ForDialect<'a, MappedFieldType>> for CreateSql {
+    fn from(fd: &ForDialect<MappedFieldType>) -> CreateSql {
+        match *fd {
+            ForDialect(Dialect::Postgres, t) => postgres_type(t),
+            ForDialect(Dialect::Sqlite, t) => sqlite_type(t),
+            ForDialect(Dialect::MySql, t) => mysql_type(t),
+        }
+    }
+}
 
 impl<'a> From<&'a MappedFieldType> for CreateSql {
     fn from(f: &MappedFieldType) -> CreateSql {
-        match f {
-            MappedFieldType::BigSerialPk => {
-               "BigSerial Primary Key".into()
+        CreateSql::from(&ForDialect(Dialect::Postgres, f))
+    }
+}
+
+fn postgres_type(f: &MappedFieldType) -> CreateSql {
+    match f {
+        MappedFieldType::BigSerialPk => {
+           "BigSerial Primary Key".into()
+        }
+        MappedFieldType::String(max_length) => {
+            format!("varchar({})", max_length).into()
+        }
+        MappedFieldType::Text => {
+            format!("Text").into()
+        }
+        MappedFieldType::Uuid => {
+            format!("Uuid").into()
+        }
+        MappedFieldType::Json => {
+            format!("Json").into()
+        }
+        MappedFieldType::Jsonb => {
+            format!("Jsonb").into()
+        }
+        MappedFieldType::Bytea => {
+            format!("Bytea").into()
+        }
+        MappedFieldType::SmallInt => {
+            format!("SmallInt").into()
+        }
+        MappedFieldType::Integer => {
+            format!("BigInt").into()
+        }
+        MappedFieldType::Numeric => {
+            format!("Numeric").into()
+        }
+        MappedFieldType::Decimal(precision, scale) => {
+            format!("Numeric({}, {})", precision, scale).into()
+        }
+        MappedFieldType::Timestamp(Some(_)) => {
+            format!("Timestamptz").into()
+        }
+        MappedFieldType::Timestamp(None) => {
+            format!("Timestamp").into()
+        }
+        MappedFieldType::Date => {
+            format!("Date").into()
+        }
+        MappedFieldType::Time => {
+            format!("Time").into()
+        }
+        MappedFieldType::Interval => {
+            format!("Interval").into()
+        }
+        // values are held to the list by a check, see table_constraints
+        MappedFieldType::Enum(choices) => {
+            let width = choices.iter().map(|c| c.0.chars().count()).max().unwrap_or(1);
+            format!("varchar({})", width).into()
+        }
+        MappedFieldType::Array(inner) => {
+            format!("{}[]", gen_create_sql(&**inner)).into()
+        }
+        MappedFieldType::Boolean => {
+            format!("Boolean").into()
+        }
+
+    }
+}
+
+// sqlite only has type affinities, everything lands on one of these
+fn sqlite_type(f: &MappedFieldType) -> CreateSql {
+    match f {
+        MappedFieldType::BigSerialPk => {
+            "INTEGER PRIMARY KEY AUTOINCREMENT".into()
+        }
+        MappedFieldType::Boolean |
+        MappedFieldType::SmallInt |
+        MappedFieldType::Integer => {
+            "INTEGER".into()
+        }
+        MappedFieldType::Numeric |
+        MappedFieldType::Decimal(_, _) => {
+            "NUMERIC".into()
+        }
+        MappedFieldType::Bytea => {
+            "BLOB".into()
+        }
+        MappedFieldType::String(_) |
+        MappedFieldType::Text |
+        MappedFieldType::Uuid |
+        MappedFieldType::Json |
+        MappedFieldType::Jsonb |
+        MappedFieldType::Timestamp(_) |
+        MappedFieldType::Date |
+        MappedFieldType::Time |
+        MappedFieldType::Interval |
+        MappedFieldType::Enum(_) |
+        MappedFieldType::Array(_) => {
+            "TEXT".into()
+        }
+    }
+}
+
+
+fn mysql_type(f: &MappedFieldType) -> CreateSql {
+    match f {
+        MappedFieldType::BigSerialPk => {
+            "BIGINT AUTO_INCREMENT PRIMARY KEY".into()
+        }
+        MappedFieldType::Boolean => {
+            "TINYINT(1)".into()
+        }
+        MappedFieldType::String(max_length) => {
+            format!("VARCHAR({})", max_length).into()
+        }
+        MappedFieldType::Text => {
+            "LONGTEXT".into()
+        }
+        MappedFieldType::Uuid => {
+            "CHAR(36)".into()
+        }
+        MappedFieldType::Json |
+        MappedFieldType::Jsonb |
+        MappedFieldType::Array(_) => {
+            "JSON".into()
+        }
+        MappedFieldType::Bytea => {
+            "LONGBLOB".into()
+        }
+        MappedFieldType::SmallInt => {
+            "SMALLINT".into()
+        }
+        MappedFieldType::Integer => {
+            "BIGINT".into()
+        }
+        // a bare DECIMAL is DECIMAL(10, 0), take the widest instead
+        MappedFieldType::Numeric => {
+            "DECIMAL(65, 30)".into()
+        }
+        MappedFieldType::Decimal(precision, scale) => {
+            format!("DECIMAL({}, {})", precision, scale).into()
+        }
+        // TIMESTAMP is stored as utc and converted to the session time zone
+        MappedFieldType::Timestamp(Some(_)) => {
+            "TIMESTAMP".into()
+        }
+        MappedFieldType::Timestamp(None) => {
+            "DATETIME".into()
+        }
+        MappedFieldType::Date => {
+            "DATE".into()
+        }
+        MappedFieldType::Time => {
+            "TIME".into()
+        }
+        MappedFieldType::Interval => {
+            "VARCHAR(255)".into()
+        }
+        MappedFieldType::Enum(choices) => {
+            format!("ENUM({})", enum_values(Dialect::MySql, choices).join(", ")).into()
+        }
+    }
+}
+
+
+fn index_name(o: &SqlOption) -> Option<&String> {
+    match o {
+        SqlOption::Index(n) => Some(n),
+        _ => None
+    }
+}
+
+fn unique_name(o: &SqlOption) -> Option<&String> {
+    match o {
+        SqlOption::Unique(n) => Some(n),
+        _ => None
+    }
+}
+
+// Collects the columns for each named index/unique option, in field order
+fn named_groups(t: &Table, pick: fn(&SqlOption) -> Option<&String>, suffix: &str) -> Vec<(String, Vec<String>)> {
+    let mut groups: Vec<(String, Vec<String>)> = vec!();
+    for f in t.1.iter() {
+        for n in f.2.sql.iter().filter_map(pick) {
+            let name = if n.len() > 0 { n.clone() } else { format!("{}_{}_{}", t.0, f.0, suffix) };
+            match groups.iter().position(|g| g.0 == name) {
+                Some(i) => groups[i].1.push(f.0.clone()),
+                None => groups.push((name, vec!(f.0.clone())))
+            }
+        }
+    }
+    groups
+}
+
+fn enum_values(d: Dialect, choices: &Vec<(String, String)>) -> Vec<String> {
+    choices.iter()
+        .map(|c| string_literal(d, &c.0))
+        .collect()
+}
+
+// (name, sql) for the UNIQUE and CHECK constraints declared on a table's fields
+pub fn table_constraints(d: Dialect, t: &Table) -> Vec<(String, String)> {
+    let mut out: Vec<(String, String)> = named_groups(t, unique_name, "key").into_iter()
+        .map(|(n, cols)| {
+            let sql = format!("CONSTRAINT {} UNIQUE ({})", quote(d, &n), quote_list(d, &cols));
+            (n, sql)
+        })
+        .collect();
+
+    for f in t.1.iter() {
+        let n = format!("{}_{}_enum", t.0, f.0);
+        match &f.1 {
+            // mysql has a native ENUM column type
+            MappedFieldType::Enum(choices) if d != Dialect::MySql => {
+                let sql = format!("CONSTRAINT {} CHECK ({} IN ({}))", quote(d, &n), quote(d, &f.0), enum_values(d, choices).join(", "));
+                out.push((n, sql));
             }
-            MappedFieldType::String(max_length) => {
-                format!("varchar({})", max_length).into()
+            MappedFieldType::Array(inner) if d == Dialect::Postgres => {
+                if let MappedFieldType::Enum(choices) = &**inner {
+                    let sql = format!("CONSTRAINT {} CHECK ({} <@ ARRAY[{}]::{})",
+                                      quote(d, &n), quote(d, &f.0), enum_values(d, choices).join(", "), gen_create_sql(&f.1));
+                    out.push((n, sql));
+                }
             }
-            MappedFieldType::Integer => {
-                format!("BigInt").into()
+            _ => {}
+        }
+
+        let checks = f.2.sql.iter().filter_map(|o| match o {
+            SqlOption::Constraint(c) => Some(c),
+            _ => None
+        });
+
+        // same numbering postgres uses for unnamed checks
+        for (i, c) in checks.enumerate() {
+            let n = if i == 0 { format!("{}_{}_check", t.0, f.0) } else { format!("{}_{}_check{}", t.0, f.0, i) };
+            let sql = format!("CONSTRAINT {} CHECK ({})", quote(d, &n), c);
+            out.push((n, sql));
+        }
+    }
+    out
+}
+
+// (name, sql) for the indexes declared on a table's fields
+// indexes are created in the namespace of their table
+pub fn table_indexes(d: Dialect, namespace: &str, t: &Table) -> Vec<(String, String)> {
+    named_groups(t, index_name, "idx").into_iter()
+        .map(|(n, cols)| {
+            let sql = format!("CREATE INDEX {} ON {} ({});", quote(d, &n), qualify(d, namespace, &t.0), quote_list(d, &cols));
+            (n, sql)
+        })
+        .collect()
+}
+
+
+// foreign_keys are extra table constraint lines, for dialects that can't
+// add them afterwards
+pub fn create_table(d: Dialect, namespace: &str, s: &Table, foreign_keys: Vec<String>) -> String {
+    let fields = &s.1;
+    let mut lines = fields
+        .iter()
+        .map(|f| {
+            gen_create_sql(&ForDialect(d, f))
+        })
+        .collect::<Vec<String>>();
+
+    let pk = fields.iter()
+        .filter(|f| f.2.primary_key && f.1 != MappedFieldType::BigSerialPk)
+        .map(|f| f.0.clone())
+        .collect::<Vec<String>>();
+    if pk.len() > 0 {
+        lines.push(format!("PRIMARY KEY ({})", quote_list(d, &pk)));
+    }
+    lines.extend(table_constraints(d, s).into_iter().map(|c| c.1));
+    lines.extend(foreign_keys);
+
+    let sql = lines.join(",\n  ");
+    let mut create = match d {
+        Dialect::MySql => format!("CREATE TABLE {} (\n  {}\n) ENGINE=InnoDB;", qualify(d, namespace, &s.0), sql),
+        _ => format!("CREATE TABLE {} (\n  {}\n);", qualify(d, namespace, &s.0), sql)
+    };
+    for i in table_indexes(d, namespace, s) {
+        create += &format!("\n{}", i.1);
+    }
+    create
+}
+
+// Only sees the table's own namespace, schema_sql also applies the
+// schema wide one
+impl<'a, 'b> From<&'b ForDialect<'a, Table>> for CreateSql {
+    fn from(fd: &ForDialect<Table>) -> CreateSql {
+        CreateSql::from(create_table(fd.0, &fd.1 .2.namespace, fd.1, vec!()))
+    }
+}
+
+impl<'a> From<&'a Table> for CreateSql {
+    fn from(s: &Table) -> CreateSql {
+        CreateSql::from(&ForDialect(Dialect::Postgres, s))
+    }
+}
+
+fn referential_action(a: ReferentialAction) -> &'static str {
+    match a {
+        ReferentialAction::NoAction => "NO ACTION",
+        ReferentialAction::Restrict => "RESTRICT",
+        ReferentialAction::Cascade => "CASCADE",
+        ReferentialAction::SetNull => "SET NULL",
+        ReferentialAction::SetDefault => "SET DEFAULT",
+    }
+}
+
+// Left at their defaults the options are left out, so a key reads the
+// same as before they were there
+fn references(d: Dialect, s: &SchemaSpec, r: &Relation, cols: &[String], to: &Cardinality) -> String {
+    let mut fk = format!("FOREIGN KEY ({}) REFERENCES {}({})", quote_list(d, cols), table_ref(d, s, to.table()), quote_list(d, &to.fields()));
+    if r.on_delete != ReferentialAction::NoAction {
+        fk += &format!(" ON DELETE {}", referential_action(r.on_delete));
+    }
+    if r.on_update != ReferentialAction::NoAction {
+        fk += &format!(" ON UPDATE {}", referential_action(r.on_update));
+    }
+    match r.deferrable {
+        Deferral::NotDeferrable => {}
+        Deferral::Immediate => fk += " DEFERRABLE INITIALLY IMMEDIATE",
+        Deferral::Deferred => fk += " DEFERRABLE INITIALLY DEFERRED",
+    }
+    fk
+}
+
+// (table, constraint) pairs, a many-to-many relation puts two on its join
+// table. Both ends can be in other namespaces so the schema comes along
+pub fn foreign_keys(d: Dialect, s: &SchemaSpec, r: &Relation) -> Vec<(String, String)> {
+    if r.is_many_to_many() {
+        let (from_cols, to_cols) = r.join_columns();
+        let j = table_ref(d, s, &r.join_table_name());
+        vec!((j.clone(), references(d, s, r, &from_cols, &r.from)),
+             (j, references(d, s, r, &to_cols, &r.to)))
+    } else {
+        let cols = r.from.fields().into_iter().cloned().collect::<Vec<String>>();
+        vec!((table_ref(d, s, r.from.table()), references(d, s, r, &cols, &r.to)))
+    }
+}
+
+pub fn add_foreign_keys(d: Dialect, s: &SchemaSpec, r: &Relation) -> String {
+    foreign_keys(d, s, r).into_iter()
+        .map(|(t, fk)| format!("ALTER TABLE {} ADD {};\n", t, fk))
+        .collect::<String>()
+}
+
+
+// PostgREST serves this namespace, as this role until someone logs in
+pub const API_NAMESPACE: &str = "api";
+pub const API_ROLE: &str = "web_anon";
+
+// Roles are shared by every database in the cluster, so one may already be there
+pub fn create_role(role: &str) -> String {
+    format!("DO $$ BEGIN\n  CREATE ROLE {} NOLOGIN;\nEXCEPTION WHEN duplicate_object THEN NULL;\nEND $$;", quote(Dialect::Postgres, role))
+}
+
+// Statements any api view needs in place first, safe to run again
+pub fn api_setup() -> Vec<String> {
+    let d = Dialect::Postgres;
+    vec!(format!("CREATE SCHEMA IF NOT EXISTS {};", quote(d, API_NAMESPACE)),
+         create_role(API_ROLE),
+         format!("GRANT USAGE ON SCHEMA {} TO {};", quote(d, API_NAMESPACE), quote(d, API_ROLE)))
+}
+
+fn privilege(p: &Permission) -> &'static str {
+    match p {
+        Permission::Select => "SELECT",
+        Permission::Insert => "INSERT",
+        Permission::Update => "UPDATE",
+        Permission::Delete => "DELETE",
+    }
+}
+
+// SELECT, UPDATE or SELECT (a, b), UPDATE (a, b)
+fn privileges<F>(d: Dialect, a: &AclSpec, columns: F) -> String
+  where F: Fn(&Permission) -> Vec<String>
+{
+    a.permissions.iter()
+        .map(|p| {
+            let cols = columns(p);
+            if cols.len() > 0 { format!("{} ({})", privilege(p), quote_list(d, &cols)) } else { privilege(p).to_string() }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn visible_columns(t: &Table) -> Vec<String> {
+    t.1.iter().filter(|f| !f.2.hidden).map(|f| f.0.clone()).collect()
+}
+
+// What the api views read, a soft deleted table's are filtered on deleted_at
+fn live_columns(t: &Table) -> Vec<String> {
+    let mut cols = visible_columns(t);
+    if t.2.soft_delete {
+        cols.push(DELETED_AT.into());
+    }
+    cols
+}
+
+fn serial_sequences(s: &SchemaSpec, t: &Table) -> Vec<String> {
+    t.1.iter()
+        .filter(|f| f.1 == MappedFieldType::BigSerialPk)
+        .map(|f| qualify(Dialect::Postgres, s.namespace_of(&t.0), &format!("{}_{}_seq", t.0, f.0)))
+        .collect()
+}
+
+fn policy(table: &str, t: &Table, a: &AclSpec, p: &Permission) -> (String, String) {
+    let d = Dialect::Postgres;
+    let name = quote(d, &format!("{}_{}_{}", t.0, a.role, privilege(p).to_lowercase()));
+    let predicate = if a.policy.len() > 0 { a.policy.as_str() } else { "true" };
+    let check = match p {
+        Permission::Select | Permission::Delete => format!("USING ({})", predicate),
+        Permission::Insert => format!("WITH CHECK ({})", predicate),
+        Permission::Update => format!("USING ({}) WITH CHECK ({})", predicate, predicate),
+    };
+    (format!("CREATE POLICY {} ON {} FOR {} TO {} {};", name, table, privilege(p), quote(d, &a.role), check),
+     format!("DROP POLICY IF EXISTS {} ON {};", name, table))
+}
+
+fn push_unique(v: &mut Vec<String>, sql: String) {
+    if !v.contains(&sql) {
+        v.push(sql);
+    }
+}
+
+// (grant, revoke) statements for the acl on one table. A policy on any
+// entry turns row level security on, after which a role sees no rows
+// without a policy of its own, so the rest get one that lets everything by.
+// Api tables are reached through their view, which reads every visible
+// column as the caller. Those roles get no way into the table's namespace,
+// keep api tables out of the default one
+pub fn table_acl(s: &SchemaSpec, t: &Table) -> (Vec<String>, Vec<String>) {
+    let d = Dialect::Postgres;
+    let acl = s.acl_for(&t.0);
+    let table = table_ref(d, s, &t.0);
+    let namespace = s.namespace_of(&t.0);
+    let rls = acl.iter().any(|a| a.policy.len() > 0);
+    let in_api = s.api.contains(&t.0);
+
+    let mut grant: Vec<String> = vec!();
+    let mut policies = vec!();
+    let mut revoke: Vec<String> = vec!();
+    if rls {
+        grant.push(format!("ALTER TABLE {} ENABLE ROW LEVEL SECURITY;", table));
+    }
+    for a in acl.iter() {
+        let role = quote(d, &a.role);
+        if namespace.len() > 0 && !in_api {
+            push_unique(&mut grant, format!("GRANT USAGE ON SCHEMA {} TO {};", quote(d, namespace), role));
+        }
+        let columns = |p: &Permission| {
+            if in_api && *p == Permission::Select && a.columns.len() > 0 { live_columns(t) } else { a.columns.clone() }
+        };
+        grant.push(format!("GRANT {} ON {} TO {};", privileges(d, a, columns), table, role));
+        push_unique(&mut revoke, format!("REVOKE ALL ON {} FROM {};", table, role));
+
+        if a.permissions.contains(&Permission::Insert) {
+            for seq in serial_sequences(s, t) {
+                push_unique(&mut grant, format!("GRANT USAGE ON SEQUENCE {} TO {};", seq, role));
+                push_unique(&mut revoke, format!("REVOKE USAGE ON SEQUENCE {} FROM {};", seq, role));
+            }
+        }
+
+        // the history holds whole rows and no policy applies to it
+        if t.2.audit && a.permissions.contains(&Permission::Select) && a.columns.len() == 0 && a.policy.len() == 0 {
+            let history = table_ref(d, s, &t.history_table_name());
+            grant.push(format!("GRANT SELECT ON {} TO {};", history, role));
+            push_unique(&mut revoke, format!("REVOKE ALL ON {} FROM {};", history, role));
+        }
+
+        // the api view reads and writes the join tables as the caller, from
+        // a trigger function that looks its tables up when it runs
+        for j in s.id_arrays(&t.0).iter().map(|a| table_ref(d, s, &a.relation.join_table_name())) {
+            let mut p = vec!();
+            if a.permissions.contains(&Permission::Select) {
+                p.push("SELECT");
             }
-            MappedFieldType::Numeric => {
-                format!("Numeric").into()
+            if a.permissions.contains(&Permission::Insert) || a.permissions.contains(&Permission::Update) {
+                p.extend(vec!("INSERT", "DELETE"));
             }
-            MappedFieldType::Timestamp(timezone) => {
-                format!("Timestamp").into()
+            if p.len() > 0 {
+                if namespace.len() > 0 {
+                    push_unique(&mut grant, format!("GRANT USAGE ON SCHEMA {} TO {};", quote(d, namespace), role));
+                }
+                grant.push(format!("GRANT {} ON {} TO {};", p.join(", "), j, role));
+                push_unique(&mut revoke, format!("REVOKE ALL ON {} FROM {};", j, role));
             }
-            MappedFieldType::Boolean => {
-                format!("Boolean").into()
+        }
+
+        if rls {
+            for p in a.permissions.iter() {
+                let (create, drop) = policy(&table, t, a, p);
+                grant.push(create);
+                policies.push(drop);
             }
+        }
 
+        // whoever can delete a row can bring it back, deleting it is the
+        // same update made from the view's trigger
+        let restores = in_api && t.2.soft_delete && a.permissions.contains(&Permission::Delete);
+        if restores {
+            if namespace.len() > 0 {
+                push_unique(&mut grant, format!("GRANT USAGE ON SCHEMA {} TO {};", quote(d, namespace), role));
+            }
+            grant.push(format!("GRANT UPDATE ({}) ON {} TO {};", quote(d, DELETED_AT), table, role));
         }
+        let can_update = acl.iter().any(|e| e.role == a.role && e.permissions.contains(&Permission::Update));
+        if restores && rls && !can_update {
+            let (create, drop) = policy(&table, t, a, &Permission::Update);
+            grant.push(create);
+            policies.push(drop);
+        }
+    }
+    if rls {
+        policies.push(format!("ALTER TABLE {} DISABLE ROW LEVEL SECURITY;", table));
     }
+    policies.extend(revoke);
+    (grant, policies)
+}
+
+// (view, sql) for each SchemaSpec.api entry, a plain view over the
+// visible columns of the table. Postgres can write through a view that
+// simple. Without an acl for the table the api role gets all of it,
+// serials need their sequence too. With one the view runs as the caller
+// (postgres 15) so the table's grants and policies still hold
+pub fn api_views(s: &SchemaSpec) -> Vec<(String, String)> {
+    let d = Dialect::Postgres;
+    s.api.iter()
+        .filter_map(|name| s.tables.iter().find(|t| t.0 == *name))
+        .flat_map(|t| {
+            let view = qualify(d, API_NAMESPACE, &t.0);
+            let cols = visible_columns(t);
+            let acl = s.acl_for(&t.0);
+            let options = if acl.len() > 0 { " WITH (security_invoker = true)" } else { "" };
+            let live = if t.2.soft_delete { format!(" WHERE {} IS NULL", quote(d, DELETED_AT)) } else { "".into() };
+            let table = table_ref(d, s, &t.0);
+            let arrays = s.id_arrays(&t.0);
+            let mut select = quote_list(d, &cols);
+            for a in arrays.iter() {
+                select += &format!(", ARRAY(SELECT {} FROM {} WHERE {} = {}.{}) AS {}",
+                                   quote(d, &a.other), table_ref(d, s, &a.relation.join_table_name()), quote(d, &a.this),
+                                   table, quote(d, &a.key), quote(d, &a.column));
+            }
+            let mut sql = format!("CREATE VIEW {}{} AS\n  SELECT {} FROM {}{};", view, options, select, table, live);
+
+            if acl.len() == 0 {
+                sql += &format!("\nGRANT SELECT, INSERT, UPDATE, DELETE ON {} TO {};", view, quote(d, API_ROLE));
+                for seq in serial_sequences(s, t) {
+                    sql += &format!("\nGRANT USAGE ON SEQUENCE {} TO {};", seq, quote(d, API_ROLE));
+                }
+            }
+            for a in acl.iter() {
+                let visible = a.columns.iter().filter(|c| cols.contains(c)).cloned().collect::<Vec<String>>();
+                if a.columns.len() > 0 && visible.len() == 0 {
+                    continue;
+                }
+                sql += &format!("\nGRANT USAGE ON SCHEMA {} TO {};", quote(d, API_NAMESPACE), quote(d, &a.role));
+                sql += &format!("\nGRANT {} ON {} TO {};", privileges(d, a, |_| visible.clone()), view, quote(d, &a.role));
+            }
+            if t.2.soft_delete {
+                sql += &format!("\n{}", soft_delete_trigger(s, t, &view));
+            }
+            if arrays.len() > 0 {
+                sql += &format!("\n{}", id_array_trigger(s, t, &view, &arrays));
+            }
+            let mut views = vec!((view, sql));
+            views.extend(history_view(s, t));
+            views.extend(deleted_view(s, t));
+            views
+        })
+        .collect()
 }
 
+// A delete through the live view of a soft deleted table sets deleted_at
+// instead. Without an acl the function runs as its owner, with one it runs
+// as the caller, whose update of deleted_at is held to the delete policy
+fn soft_delete_trigger(s: &SchemaSpec, t: &Table, view: &str) -> String {
+    let d = Dialect::Postgres;
+    let namespace = if s.namespace_of(&t.0).len() > 0 { s.namespace_of(&t.0) } else { "public" };
+    let function = qualify(d, s.namespace_of(&t.0), &format!("{}_soft_delete", t.0));
+    let security = if s.acl_for(&t.0).len() > 0 { "SECURITY INVOKER" } else { "SECURITY DEFINER" };
+    let key = quote(d, t.1.iter().find(|f| f.is_primary_key()).map(|f| f.0.as_str()).unwrap_or("id"));
 
+    format!("CREATE OR REPLACE FUNCTION {}() RETURNS trigger\n\
+             LANGUAGE plpgsql {} SET search_path = pg_catalog, pg_temp AS $body$\n\
+             BEGIN\n  \
+             UPDATE {} SET {} = now() WHERE {} = OLD.{};\n  \
+             RETURN OLD;\n\
+             END;\n\
+             $body$;\n\
+             CREATE TRIGGER soft_delete INSTEAD OF DELETE ON {}\n  \
+             FOR EACH ROW\n  \
+             EXECUTE FUNCTION {}();",
+            function, security, qualify(d, namespace, &t.0), quote(d, DELETED_AT), key, key, view, function)
+}
 
-impl<'a> From<&'a (String, Vec<Field>)> for CreateSql {
-    fn from(s: &(String, Vec<Field>)) -> CreateSql {
-        let fields = &s.1;
-        let sql: String = fields
-            .iter()
-            .map(|f| {
-                gen_create_sql(f)
-            })
-            .collect::<Vec<String>>()
-            .join(",\n  ");
-        CreateSql::from(
-            format!("CREATE TABLE {} (\n  {}\n);", s.0, sql)
-        )
+// The trigger functions behind the api views, dropping a view leaves them
+pub fn api_functions(s: &SchemaSpec) -> Vec<String> {
+    let d = Dialect::Postgres;
+    let mut out = vec!();
+    for t in s.api.iter().filter_map(|name| s.tables.iter().find(|t| t.0 == *name)) {
+        if t.2.soft_delete {
+            out.push(qualify(d, s.namespace_of(&t.0), &format!("{}_soft_delete", t.0)));
+        }
+        if s.id_arrays(&t.0).len() > 0 {
+            out.push(qualify(d, s.namespace_of(&t.0), &format!("{}_api_write", t.0)));
+        }
     }
+    out
 }
 
-// TODO(matt) - refactor this so it can be run 'online' against a 
-//              migration
-impl <'a> From<&'a Relation> for CreateSql {
-    fn from(s: &Relation) -> CreateSql {
-       let a1 = format!("ALTER TABLE {} ADD FOREIGN KEY ({}) REFERENCES {}({});", s.from.table(), s.from.field(), s.to.table(), s.to.field());
-//       let a2 = format!("ALTER TABLE {} ADD FOREIGN KEY ({}) REFERENCES {}({});", s.to.table(), s.to.field(), s.from.table(), s.from.field());
+// The id arrays are not columns of the table, so writes through a view
+// carrying them go to a trigger that writes the row and brings the join
+// tables in line with the arrays, a null array leaves them as they are.
+// The view loses the table's defaults on the way, it is given them back.
+// Without an acl the api role can only reach the view and the function
+// runs as its owner, with one it runs as the caller so policies apply
+fn id_array_trigger(s: &SchemaSpec, t: &Table, view: &str, arrays: &[IdArray]) -> String {
+    let d = Dialect::Postgres;
+    let function = qualify(d, s.namespace_of(&t.0), &format!("{}_api_write", t.0));
+    let table = table_ref(d, s, &t.0);
+    let security = if s.acl_for(&t.0).len() > 0 { "SECURITY INVOKER" } else { "SECURITY DEFINER" };
+    let key = quote(d, t.1.iter().find(|f| f.is_primary_key()).map(|f| f.0.as_str()).unwrap_or("id"));
+    let cols = t.1.iter().filter(|f| !f.2.hidden).map(|f| f.0.clone()).collect::<Vec<String>>();
+    let written = t.1.iter().filter(|f| !f.2.hidden && !f.is_computed()).map(|f| f.0.clone()).collect::<Vec<String>>();
+    let new = |c: &Vec<String>| c.iter().map(|c| format!("NEW.{}", quote(d, c))).collect::<Vec<String>>().join(", ");
+
+    let mut sync = String::new();
+    for a in arrays.iter() {
+        let join = table_ref(d, s, &a.relation.join_table_name());
+        let (this, other, ids) = (quote(d, &a.this), quote(d, &a.other), format!("NEW.{}", quote(d, &a.column)));
+        sync += &format!("  IF {} IS NOT NULL THEN\n    \
+                          DELETE FROM {} WHERE {} = NEW.{} AND {} <> ALL ({});\n    \
+                          INSERT INTO {} ({}, {}) SELECT NEW.{}, unnest({}) ON CONFLICT DO NOTHING;\n  \
+                          END IF;\n  \
+                          {} := ARRAY(SELECT {} FROM {} WHERE {} = NEW.{});\n",
+                         ids, join, this, quote(d, &a.key), other, ids, join, this, other, quote(d, &a.key), ids,
+                         ids, other, join, this, quote(d, &a.key));
+    }
+
+    let mut sql = format!("CREATE OR REPLACE FUNCTION {}() RETURNS trigger\n\
+                           LANGUAGE plpgsql {} SET search_path = pg_catalog, pg_temp AS $body$\n\
+                           BEGIN\n  \
+                           IF TG_OP = 'INSERT' THEN\n    \
+                           INSERT INTO {} ({}) VALUES ({})\n      \
+                           RETURNING {} INTO {};\n  \
+                           ELSE\n    \
+                           UPDATE {} SET ({}) = ROW({}) WHERE {} = OLD.{}\n      \
+                           RETURNING {} INTO {};\n  \
+                           END IF;\n\
+                           {}  \
+                           RETURN NEW;\n\
+                           END;\n\
+                           $body$;\n\
+                           CREATE TRIGGER api_write INSTEAD OF INSERT OR UPDATE ON {}\n  \
+                           FOR EACH ROW\n  \
+                           EXECUTE FUNCTION {}();",
+                          function, security,
+                          table, quote_list(d, &written), new(&written), quote_list(d, &cols), new(&cols),
+                          table, quote_list(d, &written), new(&written), key, key, quote_list(d, &cols), new(&cols),
+                          sync, view, function);
 
-       CreateSql::from(a1 + "\n")
+    for f in t.1.iter().filter(|f| !f.2.hidden) {
+        let default = if f.1 == MappedFieldType::BigSerialPk {
+            Some(format!("nextval('{}')", qualify(d, s.namespace_of(&t.0), &format!("{}_{}_seq", t.0, f.0)).replace("'", "''")))
+        } else if f.2.default_value.len() > 0 {
+            sql_literal(d, &f.1, &f.2.default_value).ok()
+        } else {
+            None
+        };
+        if let Some(default) = default {
+            sql += &format!("\nALTER VIEW {} ALTER COLUMN {} SET DEFAULT {};", view, quote(d, &f.0), default);
+        }
     }
+    sql
 }
 
+// The deleted rows of a soft deleted table, restored by setting deleted_at
+// back to null. A delete here is for good
+fn deleted_view(s: &SchemaSpec, t: &Table) -> Option<(String, String)> {
+    if !t.2.soft_delete {
+        return None;
+    }
 
+    let d = Dialect::Postgres;
+    let view = qualify(d, API_NAMESPACE, &t.deleted_view_name());
+    let acl = s.acl_for(&t.0);
+    let options = if acl.len() > 0 { " WITH (security_invoker = true)" } else { "" };
+    let deleted_at = quote(d, DELETED_AT);
+    let mut sql = format!("CREATE VIEW {}{} AS\n  SELECT {} FROM {} WHERE {} IS NOT NULL;",
+                          view, options, quote_list(d, &live_columns(t)), table_ref(d, s, &t.0), deleted_at);
+    if acl.len() == 0 {
+        sql += &format!("\nGRANT SELECT, UPDATE ({}), DELETE ON {} TO {};", deleted_at, view, quote(d, API_ROLE));
+    }
+    for a in acl.iter().filter(|a| a.permissions.contains(&Permission::Delete)) {
+        let sees = acl.iter().any(|e| e.role == a.role && e.permissions.contains(&Permission::Select) && e.columns.len() == 0);
+        if sees {
+            sql += &format!("\nGRANT SELECT, UPDATE ({}), DELETE ON {} TO {};", deleted_at, view, quote(d, &a.role));
+        }
+    }
+    Some((view, sql))
+}
 
+// A read only view over the history of an audited api table, the hidden
+// columns are taken back out of the rows. Readable by whoever table_acl
+// lets read the history itself
+fn history_view(s: &SchemaSpec, t: &Table) -> Option<(String, String)> {
+    let d = Dialect::Postgres;
+    let h = history_table(s, t)?;
+    let view = qualify(d, API_NAMESPACE, &h.0);
+    let live = live_columns(t);
+    let hidden = t.1.iter().filter(|f| !live.contains(&f.0)).map(|f| string_literal(d, &f.0)).collect::<Vec<String>>();
+    let strip = if hidden.len() > 0 { format!(" - ARRAY[{}]", hidden.join(", ")) } else { "".into() };
 
+    let acl = s.acl_for(&t.0);
+    let options = if acl.len() > 0 { " WITH (security_invoker = true)" } else { "" };
+    let mut sql = format!("CREATE VIEW {}{} AS\n  SELECT id, record_id, operation, changed_at, changed_by, old_row{} AS old_row, new_row{} AS new_row\n  FROM {};",
+                          view, options, strip, strip, table_ref(d, s, &h.0));
+    if acl.len() == 0 {
+        sql += &format!("\nGRANT SELECT ON {} TO {};", view, quote(d, API_ROLE));
+    }
+    for a in acl.iter().filter(|a| a.permissions.contains(&Permission::Select) && a.columns.len() == 0 && a.policy.len() == 0) {
+        sql += &format!("\nGRANT SELECT ON {} TO {};", view, quote(d, &a.role));
+    }
+    Some((view, sql))
+}
 
+fn check_acl(d: Dialect, s: &SchemaSpec) -> Result<(), String> {
+    if s.acl.len() > 0 && d != Dialect::Postgres {
+        return Err("acl: roles, grants and policies are only generated for postgres".into());
+    }
 
+    for (i, a) in s.acl.iter().enumerate() {
+        check_identifier(d, "role", &a.role).map_err(|e| format!("acl: {}", e))?;
+        let t = s.tables.iter().find(|t| t.0 == a.resource)
+            .ok_or_else(|| format!("acl: unknown table {}", a.resource))?;
 
+        if a.permissions.len() == 0 {
+            return Err(format!("acl: {} on {} grants nothing", a.role, a.resource));
+        }
+        for c in a.columns.iter() {
+            if !t.1.iter().any(|f| f.0 == *c) {
+                return Err(format!("acl: unknown column {}.{}", a.resource, c));
+            }
+        }
+        if a.columns.len() > 0 && a.permissions.contains(&Permission::Delete) {
+            return Err(format!("acl: {} on {}, Delete can not be limited to columns", a.role, a.resource));
+        }
+        for p in a.permissions.iter() {
+            let given = s.acl[..i].iter()
+                .filter(|e| e.role == a.role && e.resource == a.resource)
+                .any(|e| e.permissions.contains(p));
+            if given || a.permissions.iter().filter(|q| *q == p).count() > 1 {
+                return Err(format!("acl: {:?} for {} on {} is given twice", p, a.role, a.resource));
+            }
+        }
+    }
+    Ok(())
+}
 
+fn check_audit(d: Dialect, s: &SchemaSpec) -> Result<(), String> {
+    for t in s.tables.iter().filter(|t| t.2.audit) {
+        if d != Dialect::Postgres {
+            return Err(format!("{}: the history is written by a trigger, audit needs postgres", t.0));
+        }
+        if t.1.iter().filter(|f| f.is_primary_key()).count() != 1 {
+            return Err(format!("{}: audit needs a single column primary key to tie the history to", t.0));
+        }
+        let name = t.history_table_name();
+        check_table_name(d, &name)?;
+        if s.tables.iter().any(|o| o.0 == name) || s.relationships.iter().any(|r| r.is_many_to_many() && r.join_table_name() == name) {
+            return Err(format!("{}: history table {} clashes with another table", t.0, name));
+        }
+        if s.events.iter().any(|e| e.table == t.0 && e.event_name == "audit") {
+            return Err(format!("{}: an audited table can not have an event called audit", t.0));
+        }
+    }
+    Ok(())
+}
 
+fn check_soft_delete(s: &SchemaSpec) -> Result<(), String> {
+    for t in s.tables.iter().filter(|t| t.2.soft_delete) {
+        if !s.api.contains(&t.0) {
+            return Err(format!("{}: soft delete happens in the api views, the table has to be in the api", t.0));
+        }
+        if t.1.iter().filter(|f| f.is_primary_key()).count() != 1 {
+            return Err(format!("{}: soft delete needs a single column primary key", t.0));
+        }
+        match t.1.iter().find(|f| f.0 == DELETED_AT) {
+            Some(f) if f.2.null == Nullable::Null => match f.1 {
+                MappedFieldType::Timestamp(_) => {}
+                _ => return Err(format!("{}.{}: has to be a timestamp", t.0, DELETED_AT))
+            },
+            Some(_) => return Err(format!("{}.{}: has to be nullable, null marks a live row", t.0, DELETED_AT)),
+            None => return Err(format!("{}: no {} column, SchemaSpec::add_implied_columns was not run", t.0, DELETED_AT))
+        }
+        let name = t.deleted_view_name();
+        if s.api.contains(&name) || s.tables.iter().any(|o| o.2.audit && s.api.contains(&o.0) && o.history_table_name() == name) {
+            return Err(format!("{}: api view {} clashes with another api view", t.0, name));
+        }
+    }
+    Ok(())
+}
+
+fn check_api(d: Dialect, s: &SchemaSpec) -> Result<(), String> {
+    if s.api.len() > 0 && d != Dialect::Postgres {
+        return Err("the api is served by postgrest, it needs postgres".into());
+    }
+
+    for (i, name) in s.api.iter().enumerate() {
+        let t = s.tables.iter().find(|t| t.0 == *name)
+            .ok_or_else(|| format!("api: unknown table {}", name))?;
+        if s.api[..i].contains(name) {
+            return Err(format!("api: {} is listed twice", name));
+        }
+        if s.namespace_of(name) == API_NAMESPACE {
+            return Err(format!("api: table {} is already in the {} namespace", name, API_NAMESPACE));
+        }
+        if t.1.iter().all(|f| f.2.hidden) {
+            return Err(format!("api: every column of {} is hidden", name));
+        }
+        for a in s.id_arrays(name).iter() {
+            if t.1.iter().any(|f| f.0 == a.column) {
+                return Err(format!("api: {}.{} clashes with the ids of its many-to-many relation", name, a.column));
+            }
+            if t.1.iter().filter(|f| f.is_primary_key() && !f.2.hidden).count() != 1 {
+                return Err(format!("api: {} has a many-to-many relation, its view needs a visible single column primary key", name));
+            }
+            if t.1.iter().any(|f| f.0 == a.key && f.2.hidden) {
+                return Err(format!("api: {}.{} is hidden, the view's {} could not be kept in step", name, a.key, a.column));
+            }
+        }
+        for f in t.1.iter().filter(|f| f.2.hidden) {
+            if f.2.null == Nullable::NotNull && f.2.default_value.len() == 0 && f.1 != MappedFieldType::BigSerialPk && !f.is_computed() {
+                return Err(format!("api: {}.{} is hidden and NOT NULL without a default, rows could not be inserted", name, f.0));
+            }
+        }
+    }
+    Ok(())
+}
+
+
+fn trigger_event(k: &EventKind) -> &'static str {
+    match k {
+        EventKind::Insert => "INSERT",
+        EventKind::Update => "UPDATE",
+        EventKind::Delete => "DELETE",
+    }
+}
+
+// The channel endpoint events are announced on, the dispatcher listens here
+pub const EVENT_CHANNEL: &str = "appgen_events";
+
+// Notifications are capped at 8000 bytes and an oversized one fails the
+// write, so a row too big to send goes out without its old and new values
+fn notify_body(e: &EventSpec) -> String {
+    let d = Dialect::Postgres;
+    let head = format!("'event', {}, 'table', {}, 'op', TG_OP",
+                       string_literal(d, &e.event_name), string_literal(d, &e.table));
+    format!("payload := json_build_object({}, 'old', row_to_json(OLD), 'new', row_to_json(NEW))::text;\n  \
+             IF octet_length(payload) >= 8000 THEN\n    \
+             payload := json_build_object({}, 'truncated', true)::text;\n  \
+             END IF;\n  \
+             PERFORM pg_notify({}, payload);\n  \
+             RETURN NULL;",
+            head, head, string_literal(d, EVENT_CHANNEL))
+}
+
+// (create, drop) for an event that lives in the database, a trigger
+// function next to its table. A backend event runs its source there, a
+// before trigger lets the row through unless the source returns first and
+// what an after trigger returns is ignored. An endpoint event only
+// notifies, postgres holds the notification back until the commit
+pub fn event_sql(s: &SchemaSpec, e: &EventSpec) -> (String, String) {
+    let d = Dialect::Postgres;
+    let table = table_ref(d, s, &e.table);
+    let function = qualify(d, s.namespace_of(&e.table), &format!("{}_{}", e.table, e.event_name));
+    let trigger = quote(d, &e.event_name);
+
+    let timing = match e.timing {
+        EventTiming::Before => "BEFORE",
+        EventTiming::After => "AFTER",
+    };
+    let body = match e.event_location {
+        LocationDef::Backend => {
+            let ret = match e.timing {
+                EventTiming::Before => "IF TG_OP = 'DELETE' THEN\n    RETURN OLD;\n  END IF;\n  RETURN NEW;",
+                EventTiming::After => "RETURN NULL;",
+            };
+            format!("BEGIN\n  {}\n  {}\nEND;", e.source.trim(), ret)
+        }
+        LocationDef::Endpoint { .. } => format!("DECLARE\n  payload text;\nBEGIN\n  {}\nEND;", notify_body(e)),
+        // filtered out by database_events
+        LocationDef::Frontend => unreachable!(),
+    };
+    let on = e.on.iter().map(trigger_event).collect::<Vec<&str>>().join(" OR ");
+    let when = if e.when.len() > 0 { format!("\n  WHEN ({})", e.when) } else { "".into() };
+
+    let create = format!("CREATE FUNCTION {}() RETURNS trigger\nLANGUAGE plpgsql AS $body$\n{}\n$body$;\n\
+                          CREATE TRIGGER {} {} {} ON {}\n  FOR EACH ROW{}\n  EXECUTE FUNCTION {}();",
+                         function, body, trigger, timing, on, table, when, function);
+    let drop = format!("DROP TRIGGER IF EXISTS {} ON {};\nDROP FUNCTION IF EXISTS {}();", trigger, table, function);
+    (create, drop)
+}
+
+// The events that turn into triggers, frontend ones live in the app
+pub fn database_events(s: &SchemaSpec) -> Vec<&EventSpec> {
+    s.events.iter().filter(|e| e.event_location != LocationDef::Frontend).collect()
+}
+
+fn check_events(d: Dialect, s: &SchemaSpec) -> Result<(), String> {
+    for (i, e) in s.events.iter().enumerate() {
+        check_identifier(d, "event", &e.event_name).map_err(|e| format!("events: {}", e))?;
+        let t = s.tables.iter().find(|t| t.0 == e.table)
+            .ok_or_else(|| format!("events: {} is on unknown table {}", e.event_name, e.table))?;
+
+        if s.events[..i].iter().any(|o| o.table == e.table && o.event_name == e.event_name) {
+            return Err(format!("events: {} is declared twice on {}", e.event_name, t.0));
+        }
+        if t.2.soft_delete && e.event_name == "soft_delete" {
+            return Err(format!("events: {} is soft deleted, it can not have an event called soft_delete", t.0));
+        }
+        if s.id_arrays(&t.0).len() > 0 && e.event_name == "api_write" {
+            return Err(format!("events: {} is written through its many-to-many ids, it can not have an event called api_write", t.0));
+        }
+        if e.on.len() == 0 {
+            return Err(format!("events: {} does not say which changes it is on", e.event_name));
+        }
+
+        match e.event_location {
+            LocationDef::Backend => {
+                if d != Dialect::Postgres {
+                    return Err(format!("events: {} runs as a plpgsql trigger, it needs postgres", e.event_name));
+                }
+                if e.source.trim().len() == 0 {
+                    return Err(format!("events: {} has no source", e.event_name));
+                }
+                if e.source.contains("$body$") {
+                    return Err(format!("events: {} source can not contain $body$", e.event_name));
+                }
+            }
+            LocationDef::Endpoint { ref url } => {
+                if d != Dialect::Postgres {
+                    return Err(format!("events: {} is sent with pg_notify, it needs postgres", e.event_name));
+                }
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err(format!("events: {} needs an http or https url, not {:?}", e.event_name, url));
+                }
+                if e.timing == EventTiming::Before {
+                    return Err(format!("events: {} goes to an endpoint, it can only run after the change", e.event_name));
+                }
+                if e.source.len() > 0 {
+                    return Err(format!("events: {} goes to an endpoint, it can not have a source", e.event_name));
+                }
+            }
+            LocationDef::Frontend => {
+                let js_name = e.event_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    && !e.event_name.starts_with(|c: char| c.is_ascii_digit());
+                if !js_name {
+                    return Err(format!("events: {} runs in the app, its name has to be letters, digits and _", e.event_name));
+                }
+                if e.source.trim().len() == 0 {
+                    return Err(format!("events: {} has no source", e.event_name));
+                }
+                if e.when.len() > 0 {
+                    return Err(format!("events: {} runs in the app, when is sql and can not apply", e.event_name));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+
+// The relations with a foreign key on table, each with the table it
+// points at. A table pointing at itself waits on nothing
+fn outgoing<'a>(s: &'a SchemaSpec, table: &str) -> Vec<(&'a Relation, &'a String)> {
+    let mut out = vec!();
+    for r in s.relationships.iter() {
+        if r.is_many_to_many() {
+            if r.join_table_name() == table {
+                out.push((r, r.from.table()));
+                out.push((r, r.to.table()));
+            }
+        } else if r.from.table() == table {
+            out.push((r, r.to.table()));
+        }
+    }
+    out.into_iter().filter(|o| o.1 != table).collect()
+}
+
+// Orders tables so each comes after the ones it references, otherwise
+// keeping the order given. Err holds the tables caught in a cycle
+pub fn dependency_order(s: &SchemaSpec, tables: &[String]) -> Result<Vec<String>, Vec<String>> {
+    let mut left = tables.to_vec();
+    let mut out: Vec<String> = vec!();
+    while left.len() > 0 {
+        let ready = left.iter().position(|t| outgoing(s, t).iter().all(|o| !left.contains(o.1)));
+        match ready {
+            Some(i) => out.push(left.remove(i)),
+            None => return Err(left)
+        }
+    }
+    Ok(out)
+}
+
+// Whether from gets to to over foreign keys between the tables left,
+// leaving out the deferred ones
+fn reaches(s: &SchemaSpec, from: &str, to: &str, left: &[String], deferred: &[&Relation]) -> bool {
+    let mut seen: Vec<&str> = vec!();
+    let mut next = vec!(from);
+    while let Some(t) = next.pop() {
+        if t == to {
+            return true;
+        }
+        if seen.contains(&t) {
+            continue;
+        }
+        seen.push(t);
+        next.extend(outgoing(s, t).into_iter()
+                    .filter(|o| !deferred.contains(&o.0) && left.contains(o.1))
+                    .map(|o| o.1.as_str()));
+    }
+    false
+}
+
+// Every table, join tables too, in the order to create them in, and the
+// relations whose foreign keys have to wait until they are all there.
+// Those close a cycle, it is broken at the first table found on it
+pub fn table_order(s: &SchemaSpec) -> (Vec<String>, Vec<&Relation>) {
+    let mut left = s.tables.iter().map(|t| t.0.clone())
+        .chain(join_tables(s).into_iter().map(|t| t.0))
+        .collect::<Vec<String>>();
+    let mut out: Vec<String> = vec!();
+    let mut deferred: Vec<&Relation> = vec!();
+    while left.len() > 0 {
+        let waits_on = |t: &str, deferred: &Vec<&Relation>| outgoing(s, t).into_iter()
+            .filter(|o| !deferred.contains(&o.0) && left.contains(o.1))
+            .map(|o| o.1.clone())
+            .collect::<Vec<String>>();
+
+        if let Some(i) = left.iter().position(|t| waits_on(t, &deferred).len() == 0) {
+            out.push(left.remove(i));
+            continue;
+        }
+
+        // everything left waits on something, so following what a table
+        // waits on comes back around to a table on a cycle
+        let mut seen: Vec<String> = vec!();
+        let mut t = left[0].clone();
+        while !seen.contains(&t) {
+            seen.push(t.clone());
+            t = waits_on(&t, &deferred).remove(0);
+        }
+        let closing = outgoing(s, &t).into_iter()
+            .filter(|o| !deferred.contains(&o.0) && left.contains(o.1) && reaches(s, o.1, &t, &left, &deferred))
+            .map(|o| o.0)
+            .collect::<Vec<&Relation>>();
+        deferred.extend(closing);
+    }
+    (out, deferred)
+}
+
+// What a seed row can give a column
+struct SeedColumn {
+    name: String,
+    ty: MappedFieldType,
+    // nothing to fall back on when the row leaves it out
+    needed: bool,
+    nullable: bool,
+}
+
+// The columns of a table a seed can go in, declared or a join table.
+// Computed columns can't take a value
+fn seed_columns(s: &SchemaSpec, table: &str) -> Option<Vec<SeedColumn>> {
+    let column = |f: &Field| SeedColumn {
+        name: f.0.clone(),
+        ty: f.1.clone(),
+        needed: f.2.null == Nullable::NotNull && f.2.default_value.len() == 0 && f.1 != MappedFieldType::BigSerialPk,
+        nullable: f.2.null == Nullable::Null && !f.is_primary_key(),
+    };
+    s.tables.iter()
+        .find(|t| t.0 == table)
+        .map(|t| t.1.iter().filter(|f| !f.is_computed()).map(column).collect())
+        .or_else(|| {
+            join_tables(s).into_iter()
+                .find(|t| t.0 == table)
+                .map(|t| t.1.iter().map(column).collect())
+        })
+}
+
+// A serial takes an explicit id in a seed, so rows can point at each
+// other. Only a nullable column takes a null
+fn seed_value(d: Dialect, c: &SeedColumn, v: &SeedValue) -> Result<String, String> {
+    match (v, &c.ty) {
+        (SeedValue::Null(_), _) if c.nullable => Ok("NULL".into()),
+        (SeedValue::Null(_), _) => Err("can't be null".into()),
+        (SeedValue::Value(v), MappedFieldType::BigSerialPk) => sql_literal(d, &MappedFieldType::Integer, v),
+        (SeedValue::Value(v), t) => sql_literal(d, t, v)
+    }
+}
+
+// The seed rows as inserts, tables in foreign key order and rows in the
+// order given. Postgres sequences are moved past any ids given
+pub fn seed_sql(d: Dialect, s: &SchemaSpec) -> Result<Vec<String>, String> {
+    check_seed(d, s)?;
+
+    let mut names: Vec<String> = vec!();
+    for e in s.seed.iter() {
+        if !names.contains(&e.table) {
+            names.push(e.table.clone());
+        }
+    }
+    let order = dependency_order(s, &names).unwrap_or(names);
+
+    let mut out = vec!(match d {
+        Dialect::MySql => "START TRANSACTION;".to_string(),
+        _ => "BEGIN;".to_string()
+    });
+    for name in order.iter() {
+        let columns = seed_columns(s, name).ok_or_else(|| format!("seed: unknown table {}", name))?;
+        let table = table_ref(d, s, name);
+        for e in s.seed.iter().filter(|e| e.table == *name) {
+            for row in e.rows.iter() {
+                let given = columns.iter().filter(|c| row.contains_key(&c.name)).collect::<Vec<&SeedColumn>>();
+                let cols = given.iter().map(|c| c.name.clone()).collect::<Vec<String>>();
+                let values = given.iter()
+                    .map(|c| seed_value(d, c, &row[&c.name]))
+                    .collect::<Result<Vec<String>, String>>()?;
+                out.push(format!("INSERT INTO {} ({}) VALUES ({});", table, quote_list(d, &cols), values.join(", ")));
+            }
+        }
+
+        if d == Dialect::Postgres {
+            for c in columns.iter().filter(|c| c.ty == MappedFieldType::BigSerialPk) {
+                if seeds_column(s, name, &c.name) {
+                    let seq = qualify(d, s.namespace_of(name), &format!("{}_{}_seq", name, c.name));
+                    out.push(format!("SELECT setval({}, (SELECT max({}) FROM {}));", string_literal(d, &seq), quote(d, &c.name), table));
+                }
+            }
+        }
+    }
+    out.push("COMMIT;".into());
+    Ok(out)
+}
+
+fn seeds_column(s: &SchemaSpec, table: &str, column: &str) -> bool {
+    s.seed.iter().any(|e| e.table == table && e.rows.iter().any(|r| r.contains_key(column)))
+}
+
+fn check_seed(d: Dialect, s: &SchemaSpec) -> Result<(), String> {
+    let mut names: Vec<String> = vec!();
+    for e in s.seed.iter() {
+        let columns = seed_columns(s, &e.table)
+            .ok_or_else(|| format!("seed: unknown table {}", e.table))?;
+        for (i, row) in e.rows.iter().enumerate() {
+            for k in row.keys() {
+                let computed = s.tables.iter().find(|t| t.0 == e.table).and_then(|t| t.1.iter().find(|f| f.0 == *k)).map(|f| f.is_computed());
+                if computed == Some(true) {
+                    return Err(format!("seed: {} row {}: {} is computed, it can't be given a value", e.table, i + 1, k));
+                }
+                if !columns.iter().any(|c| c.name == *k) {
+                    return Err(format!("seed: {} row {}: unknown column {}", e.table, i + 1, k));
+                }
+            }
+            for c in columns.iter() {
+                match row.get(&c.name) {
+                    Some(v) => {
+                        seed_value(d, c, v).map_err(|err| format!("seed: {} row {}: {}, {}", e.table, i + 1, c.name, err))?;
+                    }
+                    None if c.needed => {
+                        return Err(format!("seed: {} row {}: {} needs a value", e.table, i + 1, c.name));
+                    }
+                    None => {}
+                }
+            }
+        }
+        if !names.contains(&e.table) {
+            names.push(e.table.clone());
+        }
+    }
+
+    dependency_order(s, &names)
+        .map(|_| ())
+        .map_err(|cycle| format!("seed: the foreign keys between {} go round in a cycle, there is no order to insert them in", cycle.join(", ")))
+}
+
+// Every statement for a fresh database, in the order it has to run
+pub fn schema_sql(d: Dialect, s: &SchemaSpec) -> Vec<String> {
+    let mut tables = s.tables.iter().collect::<Vec<&Table>>();
+    let joins = join_tables(s);
+    tables.extend(joins.iter());
+
+    match d {
+        Dialect::Postgres |
+        Dialect::MySql => {
+            let mut out = s.namespaces().into_iter()
+                .map(|n| format!("CREATE SCHEMA IF NOT EXISTS {};", quote(d, n)))
+                .collect::<Vec<String>>();
+            // foreign keys go in with their table unless they close a cycle
+            let (order, deferred) = table_order(s);
+            let fks = s.relationships.iter()
+                .filter(|r| !deferred.contains(r))
+                .flat_map(|r| foreign_keys(d, s, r))
+                .collect::<Vec<(String, String)>>();
+            out.extend(order.iter().map(|name| {
+                let t = tables.iter().find(|t| t.0 == *name).unwrap();
+                let inline = fks.iter().filter(|fk| fk.0 == table_ref(d, s, &t.0)).map(|fk| fk.1.clone()).collect();
+                create_table(d, s.namespace_of(&t.0), t, inline)
+            }));
+            out.extend(deferred.into_iter().map(|r| add_foreign_keys(d, s, r)));
+            out.extend(database_events(s).into_iter().map(|e| event_sql(s, e).0));
+            out.extend(s.tables.iter().filter_map(|t| audit_sql(s, t)).map(|a| a.0));
+            if s.acl.len() > 0 {
+                out.extend(s.roles().into_iter().map(create_role));
+                out.extend(s.tables.iter().flat_map(|t| table_acl(s, t).0));
+            }
+            if s.api.len() > 0 {
+                out.extend(api_setup());
+                out.extend(api_views(s).into_iter().map(|v| v.1));
+            }
+            out
+        }
+        // no ALTER TABLE ... ADD CONSTRAINT, keys go in with the table.
+        // Sqlite only looks for the referenced table on a write, so one that
+        // closes a cycle can too
+        Dialect::Sqlite => {
+            let fks = s.relationships.iter().flat_map(|r| foreign_keys(d, s, r)).collect::<Vec<(String, String)>>();
+            let mut out = vec!("PRAGMA foreign_keys = ON;".to_string());
+            out.extend(table_order(s).0.iter().map(|name| {
+                let t = tables.iter().find(|t| t.0 == *name).unwrap();
+                let inline = fks.iter().filter(|fk| fk.0 == quote(d, &t.0)).map(|fk| fk.1.clone()).collect();
+                create_table(d, "", t, inline)
+            }));
+            out
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate rson_rs;
+
+    use self::rson_rs::de::from_str;
+    use super::*;
+
+    #[test]
+    fn timestamps_are_checked_down_to_their_offset() {
+        let timestamp = MappedFieldType::Timestamp(Some("UTC".into()));
+        for v in &["2024-02-29", "2024-02-29 13:05", "2024-02-29T13:05:09", "2024-02-29 13:05:09.123456",
+                   "2024-02-29T13:05:09Z", "2024-02-29 13:05:09+02", "2024-02-29 13:05:09-0530", "2024-02-29T13:05:09.5+05:30"] {
+            assert_eq!(sql_literal(Dialect::Postgres, &timestamp, v), Ok(format!("'{}'", v)));
+        }
+        for v in &["2024-02-29x", "2024-13-01", "2024-00-10", "2024-02-32", "2024-02-29 25:00", "2024-02-29 13:60",
+                   "2024-02-29 13:05:61", "2024-02-29 13", "2024-02-29 13:05:09.", "2024-02-29 13:05.5",
+                   "2024-02-29 13:05:09+24", "2024-02-29 13:05:09+02:", "2024-02-29 13:05:09+2", "2024-02-29 13:05:09 UTC",
+                   "2024-02-29T13:05:09Z+01", "2024-02-29 ab:cd", "2024-02-29 13:05:09+0a"] {
+            assert!(sql_literal(Dialect::Postgres, &timestamp, v).is_err(), "{} should be turned down", v);
+        }
+        assert!(sql_literal(Dialect::Postgres, &MappedFieldType::Time, "12:30:00.x").is_err());
+        assert!(sql_literal(Dialect::Postgres, &MappedFieldType::Date, "2024-1-01").is_err());
+    }
+
+    // listed backwards, each references the one after it
+    const CHAIN: &str = r#"{ tables: [
+        ("chore", [("id", BigSerialPk, {}), ("kid_id", Integer, {})]),
+        ("kid", [("id", BigSerialPk, {}), ("family_id", Integer, {})]),
+        ("family", [("id", BigSerialPk, {})]),
+      ],
+      views: [], api: [], acl: [],
+      relationships: [
+        { from: Many("chore", "kid_id"), to: One("kid", "id") },
+        { from: Many("kid", "family_id"), to: One("family", "id") },
+      ] }"#;
+
+    // each references the other, and kid its own parent
+    const CYCLE: &str = r#"{ tables: [
+        ("kid", [("id", BigSerialPk, {}), ("favourite_id", Integer, {}), ("parent_id", Integer, {null: Null})]),
+        ("chore", [("id", BigSerialPk, {}), ("kid_id", Integer, {})]),
+      ],
+      views: [], api: [], acl: [],
+      relationships: [
+        { from: Many("kid", "favourite_id"), to: One("chore", "id") },
+        { from: Many("chore", "kid_id"), to: One("kid", "id") },
+        { from: Many("kid", "parent_id"), to: One("kid", "id") },
+      ] }"#;
+
+    fn names(v: &[&str]) -> Vec<String> {
+        v.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn a_chain_is_created_from_its_end() {
+        let s: SchemaSpec = from_str(CHAIN).unwrap();
+        let (order, deferred) = table_order(&s);
+        assert_eq!(order, names(&["family", "kid", "chore"]));
+        assert!(deferred.is_empty());
+        assert_eq!(dependency_order(&s, &names(&["chore", "kid", "family"])), Ok(names(&["family", "kid", "chore"])));
+    }
+
+    #[test]
+    fn a_cycle_waits_on_one_foreign_key() {
+        let s: SchemaSpec = from_str(CYCLE).unwrap();
+        let (order, deferred) = table_order(&s);
+        assert_eq!(order, names(&["kid", "chore"]));
+        assert_eq!(deferred, vec!(&s.relationships[0]));
+        assert_eq!(dependency_order(&s, &names(&["kid", "chore"])), Err(names(&["kid", "chore"])));
+
+        let sql = schema_sql(Dialect::Postgres, &s);
+        let kid = sql.iter().position(|q| q.starts_with("CREATE TABLE kid (")).unwrap();
+        let chore = sql.iter().position(|q| q.starts_with("CREATE TABLE chore (")).unwrap();
+        let favourite = sql.iter().position(|q| q == "ALTER TABLE kid ADD FOREIGN KEY (favourite_id) REFERENCES chore(id);\n").unwrap();
+        assert!(kid < chore && chore < favourite);
+        assert!(sql[kid].contains("FOREIGN KEY (parent_id) REFERENCES kid(id)"));
+        assert!(!sql[kid].contains("REFERENCES chore(id)"));
+    }
+
+    #[test]
+    fn a_self_reference_is_not_a_cycle() {
+        let s: SchemaSpec = from_str(r#"{ tables: [
+            ("kid", [("id", BigSerialPk, {}), ("parent_id", Integer, {null: Null})]),
+          ],
+          views: [], api: [], acl: [],
+          relationships: [
+            { from: Many("kid", "parent_id"), to: One("kid", "id") },
+          ] }"#).unwrap();
+        let (order, deferred) = table_order(&s);
+        assert_eq!(order, names(&["kid"]));
+        assert!(deferred.is_empty());
+        assert_eq!(dependency_order(&s, &names(&["kid"])), Ok(names(&["kid"])));
+        assert!(schema_sql(Dialect::Postgres, &s)[0].contains("FOREIGN KEY (parent_id) REFERENCES kid(id)"));
+    }
+
+    #[test]
+    fn sqlite_takes_every_foreign_key_with_its_table() {
+        let s: SchemaSpec = from_str(CYCLE).unwrap();
+        let sql = schema_sql(Dialect::Sqlite, &s);
+        assert!(!sql.iter().any(|q| q.starts_with("ALTER TABLE")));
+        let kid = sql.iter().find(|q| q.starts_with("CREATE TABLE kid (")).unwrap();
+        assert!(kid.contains("FOREIGN KEY (favourite_id) REFERENCES chore(id)"));
+        assert!(kid.contains("FOREIGN KEY (parent_id) REFERENCES kid(id)"));
+    }
+
+    const SEEDED: &str = r#"{ tables: [
+        ("kid", [("id", BigSerialPk, {}), ("name", String(64), {}), ("nickname", String(64), {null: Null})]),
+        ("chore", [("id", BigSerialPk, {}), ("name", String(64), {}), ("kid_id", Integer, {})]),
+      ],
+      views: [], api: [], acl: [],
+      relationships: [
+        { from: Many("chore", "kid_id"), to: One("kid", "id") },
+      ],
+      seed: [
+        { table: "chore", rows: [{"name": "dishes", "kid_id": "1"}] },
+        { table: "kid", rows: [{"id": "1", "name": "O'Brien", "nickname": None}] },
+      ] }"#;
+
+    #[test]
+    fn seeds_go_in_after_what_they_reference() {
+        let s: SchemaSpec = from_str(SEEDED).unwrap();
+        let sql = seed_sql(Dialect::Postgres, &s).unwrap();
+        let kid = sql.iter().position(|q| q.starts_with("INSERT INTO kid ")).unwrap();
+        let chore = sql.iter().position(|q| q.starts_with("INSERT INTO chore ")).unwrap();
+        assert!(kid < chore);
+        assert_eq!(sql.first().unwrap(), "BEGIN;");
+        assert_eq!(sql.last().unwrap(), "COMMIT;");
+        assert!(sql[kid].contains("'O''Brien'"));
+        assert!(sql[kid].ends_with(", NULL);"));
+        assert!(sql.contains(&"SELECT setval('kid_id_seq', (SELECT max(id) FROM kid));".to_string()));
+        assert!(!sql.iter().any(|q| q.contains("chore_id_seq")));
+    }
+
+    #[test]
+    fn bad_seeds_are_errors() {
+        let bad = |from: &str, to: &str| seed_sql(Dialect::Postgres, &from_str(&SEEDED.replace(from, to)).unwrap()).unwrap_err();
+        assert_eq!(bad(r#"table: "chore""#, r#"table: "chores""#), "seed: unknown table chores");
+        assert_eq!(bad(r#""kid_id": "1""#, r#""kid": "1""#), "seed: chore row 1: unknown column kid");
+        assert!(bad(r#""kid_id": "1""#, r#""kid_id": "one""#).starts_with("seed: chore row 1: kid_id, "));
+        assert_eq!(bad(r#""name": "dishes""#, r#""name": None"#), "seed: chore row 1: name, can't be null");
+        assert_eq!(bad(r#""name": "dishes", "#, ""), "seed: chore row 1: name needs a value");
+    }
+
+    // clerk deletes under a policy, boss can't delete at all
+    const SOFT_DELETED: &str = r#"{ tables: [
+        ("chore", [("id", BigSerialPk, {}), ("name", String(64), {})], {soft_delete: true}),
+      ],
+      views: [], relationships: [], api: ["chore"],
+      acl: [
+        { role: "clerk", resource: "chore", permissions: [Select] },
+        { role: "clerk", resource: "chore", permissions: [Delete], policy: "name = 'mine'" },
+        { role: "boss", resource: "chore", permissions: [Select, Update] },
+      ] }"#;
+
+    #[test]
+    fn soft_delete_runs_as_the_caller_under_an_acl() {
+        let mut s: SchemaSpec = from_str(SOFT_DELETED).unwrap();
+        s.add_implied_columns();
+        let sql = api_views(&s).into_iter().map(|v| v.1).collect::<Vec<String>>().join("\n");
+        assert!(sql.contains("CREATE OR REPLACE FUNCTION chore_soft_delete() RETURNS trigger\n\
+                              LANGUAGE plpgsql SECURITY INVOKER"));
+        assert!(sql.contains("GRANT DELETE ON api.chore TO clerk;"));
+        assert!(!sql.contains("DELETE ON api.chore TO boss"));
+
+        // the update the trigger makes is held to the delete policy
+        let (grant, _) = table_acl(&s, &s.tables[0]);
+        assert!(grant.contains(&"GRANT UPDATE (deleted_at) ON chore TO clerk;".to_string()));
+        assert!(grant.contains(&"CREATE POLICY chore_clerk_update ON chore FOR UPDATE TO clerk USING (name = 'mine') WITH CHECK (name = 'mine');".to_string()));
+        assert!(!grant.iter().any(|g| g.contains("deleted_at") && g.contains("boss")));
+
+        s.acl.clear();
+        let sql = api_views(&s).into_iter().map(|v| v.1).collect::<Vec<String>>().join("\n");
+        assert!(sql.contains("LANGUAGE plpgsql SECURITY DEFINER"));
+    }
+}
@@ -12,7 +12,7 @@ macro_rules! hashmap {
     }}
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum IKind {
     Defaulted(String),
     Named(String),
@@ -60,8 +60,22 @@ impl Import {
     }
     
 
-    fn merge(&mut self, imports: Vec<Import>) -> Vec<Import> {
-        imports
+    // One import per source, a symbol is only brought in once
+    pub fn merge(imports: Vec<Import>) -> Vec<Import> {
+        let mut out: Vec<Import> = vec!();
+        for i in imports {
+            match out.iter().position(|o| o.source == i.source) {
+                Some(p) => {
+                    for sym in i.symbols {
+                        if !out[p].symbols.contains(&sym) {
+                            out[p].symbols.push(sym);
+                        }
+                    }
+                }
+                None => out.push(i)
+            }
+        }
+        out
     }
 
     pub fn new(source: &str, symbols: Vec<&str>) -> Import {
@@ -79,9 +93,18 @@ impl Import {
 
 }
 
+fn widget_symbols(w: &WidgetDefinition) -> Vec<IKind> {
+    let mut symbols = vec!(IKind::Named(w.tag().clone()));
+    for c in w.children() {
+        symbols.extend(widget_symbols(c));
+    }
+    symbols
+}
+
+// TODO(matt) - children from another source than their parent
 impl From<WidgetDefinition> for Import {
     fn from(w: WidgetDefinition) -> Import {
-        Import { source: w.source.clone(), symbols: vec!(IKind::Named(w.tag().clone()))}
+        Import { source: w.source.clone(), symbols: widget_symbols(&w) }
     }
 }
 
@@ -114,6 +137,11 @@ impl JsxAttr {
             JsxAttr::JsxRaw(s) => {
                 format!("{}={}", k, s)
             }
+            // jsx attribute strings have no escapes and decode html
+            // entities, so anything awkward goes in as a js string
+            JsxAttr::JsxString(s) if s.contains(|c: char| c == '"' || c == '&' || c.is_control()) => {
+                format!("{}={{{}}}", k, serde_json::Value::from(s.as_str()))
+            }
             JsxAttr::JsxString(s) => {
                 format!("{}=\"{}\"", k, s)
             }
@@ -228,6 +256,34 @@ impl JsxNodes {
          Import::react_admin(vec!("SimpleForm".into(), name.clone().into())))
     }
 
+    // Edit's own delete button neither asks nor says what else goes, so
+    // the form gets a toolbar with one that does
+    fn cascading_edit(warning: &str, children: Vec<JsxNode>) -> (JsxNode, Import) {
+        let (delete, _) = JsxNodes::delete_with_confirm(warning);
+        let toolbar = JsxNode::node("Toolbar", None, vec!(JsxNode::node("SaveButton", None, vec!()), delete));
+        let form = hashmap!("toolbar".to_string() => JsxAttr::JsxRaw(format!("{{{}}}", toolbar.to_string(false, "  ".into()))));
+        (JsxNode::node("Edit",
+            Some(hashmap!("".into() => JsxAttr::Ellipsis("props".into()))),
+            vec!(JsxNode::node("SimpleForm", Some(form), children))),
+         Import::react_admin(vec!("SimpleForm".into(), "Edit".into(), "Toolbar".into(), "SaveButton".into(), "DeleteWithConfirmButton".into())))
+    }
+
+    fn delete_with_confirm(warning: &str) -> (JsxNode, Import) {
+        let hm = hashmap!("confirmContent".to_string() => JsxAttr::JsxString(warning.into()));
+        (JsxNode::node("DeleteWithConfirmButton", Some(hm), vec!()),
+         Import::react_admin(vec!("DeleteWithConfirmButton".into())))
+    }
+
+    // The list swaps between the live rows and the deleted ones, those get
+    // a restore button
+    fn soft_delete_list(deleted: &str, mut children: Vec<JsxNode>) -> (JsxNode, Import) {
+        children.push(JsxNode::node("RestoreButton", None, vec!()));
+        let hm = hashmap!("".into() => JsxAttr::Ellipsis("props".into()),
+                          "deletedResource".into() => JsxAttr::JsxString(deleted.into()));
+        (JsxNode::node("SoftDeleteList", Some(hm), vec!(JsxNode::node("Datagrid", None, children))),
+         Import { source: "./softDelete".into(), symbols: vec!(IKind::Named("SoftDeleteList".into()), IKind::Named("RestoreButton".into())) })
+    }
+
     fn datagrid(name: &str, children: Vec<JsxNode>) -> (JsxNode, Import) {
         (JsxNode::node(name.clone(),
             Some(hashmap!("".into() => JsxAttr::Ellipsis("props".into()))),
@@ -239,6 +295,13 @@ impl JsxNodes {
 
 pub fn field_as_jsx(table: String, field: &Field, view: &ViewSpec) -> Option<(JsxNode, Import)> {
     //TODO(matt) - visibilty
+    if field.2.hidden {
+        return None;
+    }
+    // there is nothing to show until the row is written
+    if field.is_computed() && view.view == ViewKind::Create {
+        return None;
+    }
     let widget = field.1.view_map_type(&view.view, &field.2);
     let mut hm = HashMap::new();
     hm.insert("source".into(), JsxAttr::JsxString(field.0.clone()));
@@ -246,19 +309,105 @@ pub fn field_as_jsx(table: String, field: &Field, view: &ViewSpec) -> Option<(Js
         hm.insert("label".into(), JsxAttr::JsxString(field.2.label.clone()));
     }
 
-    Some((JsxNode::node(widget.tag(), Some(hm), vec!()), widget.into()))
+    Some((widget_as_jsx(&widget, hm), widget.into()))
+}
+
+fn widget_as_jsx(w: &WidgetDefinition, mut hm: HashMap<String, JsxAttr>) -> JsxNode {
+    for (k, v) in w.attrs() {
+        hm.insert(k.clone(), JsxAttr::JsxVariableRef(v.clone()));
+    }
+    let children = w.children().iter().map(|c| widget_as_jsx(c, HashMap::new())).collect();
+    JsxNode::node(w.tag(), Some(hm), children)
 }
 
 
 
-pub fn top_level_view_node(tables: &Vec<(String, Vec<Field>)>, view: &ViewSpec) -> (String, JsxNode, Vec<Import>) {
-    let tt = tables.iter().find(|i| i.0 == view.source.name);
+fn option_text(schema: &SchemaSpec, table: &str) -> String {
+    schema.tables.iter()
+        .find(|t| t.0 == table)
+        .and_then(|t| t.1.iter().find(|f| match f.1 { MappedFieldType::String(_) => true, _ => false }))
+        .map(|f| f.0.clone())
+        .unwrap_or("id".into())
+}
+
+// One widget per many-to-many relation touching the table, keyed by the
+// <other table>_ids array its api view carries. Filters are left out, the
+// array holds every id and would have to contain the ones picked
+pub fn many_to_many_as_jsx(schema: &SchemaSpec, table: &str, view: &ViewSpec) -> Vec<(String, (JsxNode, Import))> {
+    if view.view == ViewKind::Filter {
+        return vec!();
+    }
+    schema.id_arrays(table).into_iter()
+        .map(|a| (a.column, a.table))
+        .map(|(source, other)| {
+            let text = option_text(schema, &other);
+            let hm = hashmap!("source".to_string() => JsxAttr::JsxString(source.clone()),
+                              "reference".to_string() => JsxAttr::JsxString(other.clone()));
+
+            let w = match view.view {
+                ViewKind::Create | ViewKind::Edit => {
+                    let choice = hashmap!("optionText".to_string() => JsxAttr::JsxString(text));
+                    (JsxNode::node("ReferenceArrayInput", Some(hm), vec!(JsxNode::node("SelectArrayInput", Some(choice), vec!()))),
+                     Import::react_admin(vec!("ReferenceArrayInput".into(), "SelectArrayInput".into())))
+                }
+                _ => {
+                    let chip = hashmap!("source".to_string() => JsxAttr::JsxString(text));
+                    let list = JsxNode::node("SingleFieldList", None, vec!(JsxNode::node("ChipField", Some(chip), vec!())));
+                    (JsxNode::node("ReferenceArrayField", Some(hm), vec!(list)),
+                     Import::react_admin(vec!("ReferenceArrayField".into(), "SingleFieldList".into(), "ChipField".into())))
+                }
+            };
+            (source, w)
+        })
+        .collect()
+}
+
+
+// Show lists the changes to an audited record, newest first, from the
+// <table>_history resource. Only the api serves that
+pub fn history_as_jsx(schema: &SchemaSpec, table: &Table, view: &ViewSpec) -> Option<(JsxNode, Import)> {
+    if !table.2.audit || !schema.api.contains(&table.0) || view.view != ViewKind::Show {
+        return None;
+    }
+
+    let sort = hashmap!("field".to_string() => "'changed_at'".to_string(), "order".to_string() => "'DESC'".to_string());
+    let hm = hashmap!("label".to_string() => JsxAttr::JsxString("History".into()),
+                      "reference".to_string() => JsxAttr::JsxString(table.history_table_name()),
+                      "target".to_string() => JsxAttr::JsxString("record_id".into()),
+                      "sort".to_string() => JsxAttr::JsxRaw(format!("{{{}}}", JsxAttr::Object(sort).to_string(""))));
+    let changed_at = hashmap!("source".to_string() => JsxAttr::JsxString("changed_at".into()),
+                              "showTime".to_string() => JsxAttr::Bool(true));
+    let text = |source: &str| JsxNode::node("TextField", Some(hashmap!("source".to_string() => JsxAttr::JsxString(source.into()))), vec!());
+    let grid = JsxNode::node("Datagrid", None, vec!(
+        JsxNode::node("DateField", Some(changed_at), vec!()),
+        text("operation"),
+        text("changed_by")));
+
+    Some((JsxNode::node("ReferenceManyField", Some(hm), vec!(grid)),
+          Import::react_admin(vec!("ReferenceManyField".into(), "Datagrid".into(), "DateField".into(), "TextField".into()))))
+}
+
+
+// What deleting a row takes with it. A soft delete only sets deleted_at,
+// the cascades wait for the purge
+pub fn cascade_warning(schema: &SchemaSpec, table: &Table) -> Option<String> {
+    let cascaded = schema.cascaded_tables(&table.0);
+    if cascaded.len() == 0 || table.2.soft_delete {
+        return None;
+    }
+    Some(format!("This also deletes the {} records that refer to it.", cascaded.join(", ")))
+}
+
+
+pub fn top_level_view_node(schema: &SchemaSpec, view: &ViewSpec) -> (String, JsxNode, Vec<Import>) {
+    let tt = schema.tables.iter().find(|i| i.0 == view.source.name);
     
     if let Some(target_table) = tt {
 
         let mut cmap = target_table.1.iter()
             .filter_map(|i| field_as_jsx(target_table.0.clone(), &i, view).map(|o| { (i.0.clone(), o)}))
             .collect::<HashMap<String, (JsxNode, Import)>>();
+        cmap.extend(many_to_many_as_jsx(schema, &target_table.0, view));
 
         let (mut children, mut imports): (Vec<JsxNode>, Vec<Import>) =
             if view.field_order.len() > 0 {
@@ -269,17 +418,24 @@ pub fn top_level_view_node(tables: &Vec<(String, Vec<Field>)>, view: &ViewSpec)
                 cmap.drain().map(|(_, v)| v).unzip()
             };
 
-        let actions = view.actions.iter().map(|a| {
-            //TODO(matt) - params
-            let n = a.name.clone() + "Button";
-            JsxNode::node(&n, Some(HashMap::new()), vec!())
-        });
+        // the deleted rows only have a view in the api
+        let soft_deleted = target_table.2.soft_delete && schema.api.contains(&target_table.0);
+        let warning = cascade_warning(schema, target_table);
+        let (actions, aimports): (Vec<JsxNode>, Vec<Import>) = view.actions.iter().map(|a| {
+            match warning {
+                Some(ref w) if a.name == "Delete" => JsxNodes::delete_with_confirm(w),
+                //TODO(matt) - params
+                _ => {
+                    let n = a.name.clone() + "Button";
+                    (JsxNode::node(&n, Some(HashMap::new()), vec!()), Import::react_admin(vec!(n.clone())))
+                }
+            }
+        }).unzip();
 
-        let aimports = view.actions.iter().map(|a| {
-            //TODO(matt) - params
-            let n = a.name.clone() + "Button";
-            Import::react_admin(vec!(n.clone()))
-        });
+        if let Some((node, import)) = history_as_jsx(schema, target_table, view) {
+            children.push(node);
+            imports.push(import);
+        }
 
         // let (a1, a2) = actions.unzip();
         children.extend(actions);
@@ -292,6 +448,9 @@ pub fn top_level_view_node(tables: &Vec<(String, Vec<Field>)>, view: &ViewSpec)
             ViewKind::Create => { 
                 JsxNodes::simple_form("Create", children)
             } 
+            ViewKind::Edit if warning.is_some() => {
+                JsxNodes::cascading_edit(warning.as_ref().unwrap(), children)
+            }
             ViewKind::Edit => {
                 JsxNodes::simple_form("Edit", children)
             }
@@ -301,6 +460,9 @@ pub fn top_level_view_node(tables: &Vec<(String, Vec<Field>)>, view: &ViewSpec)
             ViewKind::Filter => {
                 JsxNodes::simple_form("Filter", children)
             }
+            ViewKind::List if soft_deleted => {
+                JsxNodes::soft_delete_list(&target_table.deleted_view_name(), children)
+            }
             ViewKind::List => {
                 JsxNodes::datagrid("List", children)
             }
@@ -310,7 +472,10 @@ pub fn top_level_view_node(tables: &Vec<(String, Vec<Field>)>, view: &ViewSpec)
         };
 
         imports.push(i);
-        (view.view.full_view_name(&target_table.0), n, imports)
+        if view.view == ViewKind::List && soft_deleted {
+            imports.push(Import::react_admin(vec!("Datagrid".into())));
+        }
+        (view.view.full_view_name(&target_table.0), n, Import::merge(imports))
     } else {
         ("".into(), JsxNode::Text("No Node (No Table)".into()), vec!())
 
@@ -320,3 +485,233 @@ pub fn top_level_view_node(tables: &Vec<(String, Vec<Field>)>, view: &ViewSpec)
 
 
 
+
+
+const SOFT_DELETE: &str = r#"// Generated from the schema, edit that instead
+import React, { Component } from 'react';
+import CardActions from '@material-ui/core/CardActions';
+import { List, Button, UPDATE, withDataProvider } from 'react-admin';
+
+// List hands its actions props of its own, only these are used
+const Toggle = ({ showDeleted, onToggle }) => (
+  <CardActions>
+    <Button label={showDeleted ? 'Show active' : 'Show deleted'} onClick={onToggle} />
+  </CardActions>
+);
+
+// Lists the live rows of a soft deleted resource, or the deleted ones from
+// its _deleted resource
+export class SoftDeleteList extends Component {
+  constructor(props) {
+    super(props);
+    this.state = { showDeleted: false };
+  }
+
+  render() {
+    const { deletedResource, children, ...props } = this.props;
+    const { showDeleted } = this.state;
+    return (
+      <List
+        {...props}
+        key={showDeleted ? 'deleted' : 'active'}
+        resource={showDeleted ? deletedResource : props.resource}
+        actions={<Toggle showDeleted={showDeleted} onToggle={() => this.setState({ showDeleted: !showDeleted })} />}
+        bulkActionButtons={showDeleted ? false : undefined}
+      >
+        {children}
+      </List>
+    );
+  }
+}
+
+// Only shows on deleted rows, live ones have no deleted_at. The row leaves
+// the list once it is restored
+class Restore extends Component {
+  constructor(props) {
+    super(props);
+    this.state = { loading: false };
+    this.restore = this.restore.bind(this);
+  }
+
+  restore(e) {
+    const { dataProvider, record, resource } = this.props;
+    e.stopPropagation();
+    this.setState({ loading: true });
+    dataProvider(UPDATE, resource, { id: record.id, data: { deleted_at: null }, previousData: record }, {
+      onSuccess: { notification: { body: 'Restored', level: 'info' }, refresh: true },
+      onFailure: { notification: { body: 'ra.notification.http_error', level: 'warning' } },
+    }).catch(() => this.setState({ loading: false }));
+  }
+
+  render() {
+    const { record } = this.props;
+    if (!record || !record.deleted_at) {
+      return null;
+    }
+    return <Button label="Restore" disabled={this.state.loading} onClick={this.restore} />;
+  }
+}
+
+export const RestoreButton = withDataProvider(Restore);
+"#;
+
+pub fn soft_delete_js() -> String {
+    SOFT_DELETE.into()
+}
+
+
+const ARRAYS: &str = r#"// Generated from the schema, edit that instead
+import React from 'react';
+import { addField, FieldTitle } from 'react-admin';
+import Button from '@material-ui/core/Button';
+import Chip from '@material-ui/core/Chip';
+import FormControl from '@material-ui/core/FormControl';
+import FormLabel from '@material-ui/core/FormLabel';
+import TextField from '@material-ui/core/TextField';
+
+// An array of scalars as chips, enum arrays show their labels
+export const TextArrayField = ({ record, source, choices }) => {
+  const label = (v) => {
+    const c = choices && choices.find((c) => c.id === v);
+    return c ? c.name : String(v);
+  };
+  return (
+    <span>
+      {((record && record[source]) || []).map((v, i) => (
+        <Chip key={i} label={label(v)} style={{ margin: 4 }} />
+      ))}
+    </span>
+  );
+};
+TextArrayField.defaultProps = { addLabel: true };
+
+// One text box per item, each edits its place in the array
+const ArrayOfInputs = ({ input, label, source, resource, type = 'text' }) => {
+  const items = Array.isArray(input.value) ? input.value : [];
+  const value = (v) => (type === 'number' ? (v === '' ? null : Number(v)) : v);
+  const set = (i, v) => input.onChange(items.map((item, j) => (j === i ? value(v) : item)));
+  return (
+    <FormControl margin="normal">
+      <FormLabel>
+        <FieldTitle label={label} source={source} resource={resource} />
+      </FormLabel>
+      {items.map((item, i) => (
+        <div key={i}>
+          <TextField type={type} value={item === null ? '' : item} onChange={(e) => set(i, e.target.value)} />
+          <Button size="small" onClick={() => input.onChange(items.filter((_, j) => j !== i))}>Remove</Button>
+        </div>
+      ))}
+      <Button size="small" onClick={() => input.onChange(items.concat([type === 'number' ? null : '']))}>Add</Button>
+    </FormControl>
+  );
+};
+export const TextArrayInput = addField(ArrayOfInputs);
+"#;
+
+pub fn arrays_js() -> String {
+    ARRAYS.into()
+}
+
+pub fn array_tables(s: &SchemaSpec) -> Vec<&Table> {
+    s.tables.iter()
+        .filter(|t| t.1.iter().any(|f| !f.2.hidden && match f.1 { MappedFieldType::Array(_) => true, _ => false }))
+        .collect()
+}
+
+
+// The forms have no inputs for computed columns but Edit sends back the
+// whole record, and postgres refuses any value for one
+const COMPUTED: &str = r#"
+const strip = (resource, data) => {
+  if (!COMPUTED[resource] || !data) {
+    return data;
+  }
+  const out = { ...data };
+  COMPUTED[resource].forEach((c) => delete out[c]);
+  return out;
+};
+
+export const withoutComputed = (dataProvider) => (type, resource, params) => {
+  switch (type) {
+    case CREATE:
+    case UPDATE:
+    case UPDATE_MANY:
+      return dataProvider(type, resource, { ...params, data: strip(resource, params.data) });
+    default:
+      return dataProvider(type, resource, params);
+  }
+};
+"#;
+
+pub fn computed_tables(s: &SchemaSpec) -> Vec<&Table> {
+    s.tables.iter().filter(|t| t.1.iter().any(|f| f.is_computed())).collect()
+}
+
+pub fn computed_js(s: &SchemaSpec) -> String {
+    let entries = computed_tables(s).iter().map(|t| {
+        let columns = t.1.iter().filter(|f| f.is_computed()).map(|f| serde_json::Value::from(f.0.as_str()).to_string()).collect::<Vec<String>>();
+        format!("  {}: [{}],", serde_json::Value::from(t.0.as_str()), columns.join(", "))
+    }).collect::<Vec<String>>().join("\n");
+    format!("// Generated from the schema, edit that instead\n\
+             import {{ CREATE, UPDATE, UPDATE_MANY }} from 'react-admin';\n\n\
+             const COMPUTED = {{\n{}\n}};\n{}", entries, COMPUTED)
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate rson_rs;
+
+    use self::rson_rs::de::from_str;
+    use super::*;
+
+    fn render(f: &Field, view: ViewKind) -> (String, String) {
+        let v = ViewSpec { view: view, ..Default::default() };
+        let (node, import) = field_as_jsx("t".into(), f, &v).unwrap();
+        (node.to_string(false, "".into()), import.to_string())
+    }
+
+    #[test]
+    fn scalar_arrays_bind_their_items() {
+        let tags = Field("tags".into(), MappedFieldType::Array(Box::new(MappedFieldType::String(32))), Default::default());
+        let (jsx, import) = render(&tags, ViewKind::Edit);
+        assert_eq!(jsx, "<TextArrayInput source=\"tags\"/>");
+        assert_eq!(import, "import {TextArrayInput} from './arrays';");
+
+        let (jsx, import) = render(&tags, ViewKind::List);
+        assert_eq!(jsx, "<TextArrayField source=\"tags\"/>");
+        assert_eq!(import, "import {TextArrayField} from './arrays';");
+
+        let counts = Field("counts".into(), MappedFieldType::Array(Box::new(MappedFieldType::Integer)), Default::default());
+        let (jsx, _) = render(&counts, ViewKind::Edit);
+        assert!(jsx.starts_with("<TextArrayInput "));
+        assert!(jsx.contains("type={\"number\"}"));
+        assert!(jsx.contains("source=\"counts\""));
+    }
+
+    #[test]
+    fn enum_arrays_pick_from_their_choices() {
+        let choices = vec!(("mon".to_string(), "Monday".to_string()));
+        let days = Field("days".into(), MappedFieldType::Array(Box::new(MappedFieldType::Enum(choices))), Default::default());
+        let (jsx, import) = render(&days, ViewKind::Edit);
+        assert!(jsx.starts_with("<SelectArrayInput "));
+        assert!(jsx.contains("choices={[{ id: 'mon', name: 'Monday' }]}"));
+        assert_eq!(import, "import {SelectArrayInput} from 'react-admin';");
+
+        let (jsx, _) = render(&days, ViewKind::Show);
+        assert!(jsx.starts_with("<TextArrayField "));
+        assert!(jsx.contains("choices={[{ id: 'mon', name: 'Monday' }]}"));
+    }
+
+    #[test]
+    fn history_is_only_shown_for_api_tables() {
+        let chore = Table("chore".into(), vec!(), TableOptions { audit: true, ..Default::default() });
+        let show = ViewSpec { view: ViewKind::Show, ..Default::default() };
+        let mut schema: SchemaSpec = from_str("{ tables: [], views: [], relationships: [], api: [], acl: [] }").unwrap();
+        assert!(history_as_jsx(&schema, &chore, &show).is_none());
+
+        schema.api.push("chore".into());
+        let (node, _) = history_as_jsx(&schema, &chore, &show).unwrap();
+        assert!(node.to_string(false, "".into()).contains("reference=\"chore_history\""));
+    }
+}